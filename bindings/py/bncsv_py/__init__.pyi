@@ -1,14 +1,19 @@
 from typing import Iterator
 from pathlib import Path
 from io import BytesIO, StringIO
-def encode(input: Iterator[bytes[1], None, None], writer : BytesIO) -> None:
+def encode(input: Iterator[bytes[1], None, None], writer : BytesIO, delimiter : str = ",", decimal_comma : bool = False) -> None:
     pass
        
 def decode(input: Iterator[bytes[1], None, None], writer : StringIO) -> None:
     pass
 
+def encode_bytes(input: bytes, delimiter : str = ",", decimal_comma : bool = False) -> bytes:
+    pass
+
+def decode_bytes(input: bytes) -> bytes:
+    pass
+
 class Controller:
-    CHUNK_SIZE = 4096
     # Example usage : 
     # >> BnCsvController(BnCsvController.from_csv("./bncsv-core/tests/data.csv")).to_csv()
     def __init__(self, data : Path | bytes | str):
@@ -18,5 +23,5 @@ class Controller:
         pass
 
     @staticmethod
-    def from_csv(csv_path : Path | str | BytesIO | bytes) -> bytes:
+    def from_csv(csv_path : Path | str | BytesIO | bytes, delimiter : str = ",", decimal_comma : bool = False) -> bytes:
         pass
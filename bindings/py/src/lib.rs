@@ -5,10 +5,13 @@ use pyo3::prelude::*;
 //use bncsv_core::functional::fmt::utf8::Utf8Converter;
 
 #[pymodule]
+#[allow(clippy::useless_conversion)] // from the `PyResult` conversions `#[pyfunction]` generates
 mod rs_api {
 
     use bncsv_core::{
-        compr::BnCsvConverter, fmt::utf8::Utf8Converter, utils::iterators::TryChunks,
+        compr::BnCsvConverter,
+        fmt::utf8::{EncodingOptions, Utf8Converter},
+        utils::iterators::TryChunks,
     };
     use pyo3::{
         exceptions,
@@ -16,14 +19,33 @@ mod rs_api {
         types::{PyBytes, PyIterator},
     };
 
+    fn encoding_options(delimiter: char, decimal_comma: bool) -> PyResult<EncodingOptions> {
+        let delimiter = u8::try_from(delimiter).map_err(|_| {
+            exceptions::PyValueError::new_err("The delimiter must be an ASCII char")
+        })?;
+        Ok(EncodingOptions {
+            delimiter,
+            decimal_mark: if decimal_comma { b',' } else { b'.' },
+            ..EncodingOptions::default()
+        })
+    }
+
     #[pyfunction]
-    fn encode(py: Python<'_>, input: Py<PyIterator>, writer: Py<PyAny>) -> PyResult<()> {
+    #[pyo3(signature = (input, writer, delimiter=',', decimal_comma=false))]
+    fn encode(
+        py: Python<'_>,
+        input: Py<PyIterator>,
+        writer: Py<PyAny>,
+        delimiter: char,
+        decimal_comma: bool,
+    ) -> PyResult<()> {
+        let options = encoding_options(delimiter, decimal_comma)?;
         let data_iter = input.bind(py).clone().map(|el| {
             el.expect("Failed to read input")
                 .extract::<u8>()
                 .expect("Cannot read input byte")
         });
-        Utf8Converter::encode(data_iter)
+        Utf8Converter::encode_with_options(&options, data_iter)
             .try_chunks(4096)
             .try_for_each(|x| match x {
                 Ok(chunk) => {
@@ -56,4 +78,30 @@ mod rs_api {
                 Err(e) => Err(exceptions::PyBufferError::new_err(e.to_string())),
             })
     }
+
+    #[pyfunction]
+    #[pyo3(signature = (input, delimiter=',', decimal_comma=false))]
+    fn encode_bytes<'py>(
+        py: Python<'py>,
+        input: &[u8],
+        delimiter: char,
+        decimal_comma: bool,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        // Whole buffer version of `encode`, without a Python call per byte
+        let mut output = Vec::new();
+        Utf8Converter::encode_into_with_options(
+            &encoding_options(delimiter, decimal_comma)?,
+            input,
+            &mut output,
+        )
+        .map_err(|e| exceptions::PyBufferError::new_err(e.to_string()))?;
+        Ok(PyBytes::new_bound(py, &output))
+    }
+
+    #[pyfunction]
+    fn decode_bytes<'py>(py: Python<'py>, input: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        let output = Utf8Converter::decode_slice(input)
+            .map_err(|e| exceptions::PyBufferError::new_err(e.to_string()))?;
+        Ok(PyBytes::new_bound(py, &output))
+    }
 }
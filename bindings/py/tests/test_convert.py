@@ -1,8 +1,35 @@
 import bncsv_py
+from io import BytesIO
+from bncsv_py.rs_api import encode, decode, encode_bytes, decode_bytes
 
 class TestPyConvert:
     def test_py_encode_decode(self):
         csv_bytes = b'42.91,46.02,87.53\n65.55,31.57,3.79\n28.15,42.25,61.99\n13.86,22.85,94.43\n'
-        gt_compressed = b'\x9eQFq?\x1b\xf45\x9c\x08\x03\xb4\x87\x9d_\x0c\xf5\xaa\x06yp9J!\x9a\xd5x{\x95\x83\x12\xa7Y`'
+        gt_compressed = b'BNCS\x01\x03\x04\x9eQFq?\x1b\xf45\x9c\x08\x03\xb4\x87\x9d_\x0c\xf5\xaa\x06yp9J!\x9a\xd5x{\x95\x83\x12\xa7Y`\xfe\x91n\xeb'
         assert bncsv_py.Controller.from_csv(csv_bytes) == gt_compressed
-        assert bncsv_py.Controller(gt_compressed).to_csv() == csv_bytes
\ No newline at end of file
+        assert bncsv_py.Controller(gt_compressed).to_csv() == csv_bytes
+    def test_py_delimiter(self):
+        csv_bytes = b'42,91;-46,02;87,53\n65,55;31,57;3,79\n'
+        compressed = bncsv_py.Controller.from_csv(csv_bytes, delimiter=";", decimal_comma=True)
+        assert bncsv_py.Controller(compressed).to_csv() == csv_bytes
+        tsv_bytes = b'42.91\t46.02\t87.53\n65.55\t31.57\t3.79\n'
+        compressed = bncsv_py.Controller.from_csv(tsv_bytes, delimiter="\t")
+        assert bncsv_py.Controller(compressed).to_csv() == tsv_bytes
+    def test_py_bytes_match_iterators(self):
+        csv_bytes = b'42.91,46.02,87.53\n65.55,31.57,3.79\n28.15,42.25,61.99\n13.86,22.85,94.43\n'
+        compressed = BytesIO()
+        encode(iter(csv_bytes), compressed)
+        assert encode_bytes(csv_bytes) == compressed.getvalue()
+        decoded = BytesIO()
+        decode(iter(compressed.getvalue()), decoded)
+        assert decode_bytes(compressed.getvalue()) == decoded.getvalue() == csv_bytes
+        # Concatenated streams are decoded one after the other
+        assert decode_bytes(compressed.getvalue() + encode_bytes(csv_bytes)) == csv_bytes * 2
+    def test_py_decode_bytes_error(self):
+        compressed = encode_bytes(b'42.91,46.02,87.53\n')
+        for data in (b'not a bncsv file', compressed[:-3], compressed + b'junk'):
+            try:
+                decode_bytes(data)
+            except BufferError:
+                continue
+            raise AssertionError("decode_bytes accepted invalid data")
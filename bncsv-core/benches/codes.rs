@@ -0,0 +1,67 @@
+// Throughput of the hard-coded codebook, run with `cargo bench -p bncsv-core`.
+// The csv is generated with a fixed seed, so that runs can be compared.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use bncsv_core::compr::BnCsvConverter;
+use bncsv_core::fmt::utf8::Utf8Converter;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const ROWS: usize = 100_000;
+const COLUMNS: usize = 8;
+const RUNS: usize = 5;
+
+fn random_csv() -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(0);
+    (0..ROWS)
+        .map(|_| {
+            let row = (0..COLUMNS)
+                .map(|_| rng.gen_range(-1000.0..1000.0_f64).to_string())
+                .collect::<Vec<String>>();
+            row.join(",") + "\n"
+        })
+        .collect::<String>()
+        .into_bytes()
+}
+
+fn bench<T>(name: &str, input_len: usize, mut run: impl FnMut() -> T) -> T {
+    // Best of a few runs, in MB/s of csv
+    let mut best = Duration::MAX;
+    let mut output = None;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        output = Some(black_box(run()));
+        best = best.min(start.elapsed());
+    }
+    println!(
+        "{:<20} {:>8.1} MB/s",
+        name,
+        input_len as f64 / best.as_secs_f64() / 1e6
+    );
+    output.unwrap()
+}
+
+fn main() {
+    let csv = random_csv();
+    let encoded = bench("encode (iterator)", csv.len(), || {
+        Utf8Converter::encode(csv.iter().copied())
+            .collect::<std::io::Result<Vec<u8>>>()
+            .unwrap()
+    });
+    bench("encode (slice)", csv.len(), || {
+        let mut out = Vec::new();
+        Utf8Converter::encode_into(&csv, &mut out).unwrap();
+        out
+    });
+    let decoded = bench("decode (iterator)", csv.len(), || {
+        Utf8Converter::decode(encoded.iter().copied())
+            .collect::<std::io::Result<Vec<u8>>>()
+            .unwrap()
+    });
+    bench("decode (slice)", csv.len(), || {
+        let mut out = Vec::new();
+        Utf8Converter::decode_into(&encoded, &mut out).unwrap();
+        out
+    });
+    assert_eq!(decoded, csv);
+}
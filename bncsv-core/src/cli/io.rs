@@ -1,25 +1,159 @@
-use std::io::Write;
+use std::{
+    fs::File,
+    io::{self, BufReader, Cursor, Read, Write},
+    path::Path,
+};
 
-use crate::{compr::BnCsvConverter, fmt::utf8::Utf8Converter};
+use bncsv_core::fmt::stream::BncsvDecoder;
+use bncsv_core::fmt::utf8::{DecodingOptions, EncodingOptions, Utf8Converter};
+use bncsv_core::utils::iterators::TryChunks;
 
 use super::{utils, Cli, FormatType};
 
+// Inputs up to this many bytes are converted in memory, larger ones as they are read
+const IN_MEMORY_LEN: u64 = 16 << 20;
+// Encoded bytes written at once when streaming
+const OUTPUT_CHUNK_LEN: usize = 1 << 16;
+
 impl Cli {
     pub(crate) fn write_to_output(
         &self,
-        reader: Box<dyn Iterator<Item = std::io::Result<u8>>>,
+        input: impl Read,
         mut writer: Box<dyn Write>,
     ) -> std::io::Result<()> {
-        let input_iter = reader.map(|x| x.expect("Could not read input byte"));
-        utils::consume_iter_in_writer(
-            {
-                match self.input_type {
-                    FormatType::Csv => Box::new(Utf8Converter::encode(input_iter))
-                        as Box<dyn Iterator<Item = std::io::Result<u8>>>,
-                    FormatType::Bncsv => Box::new(Utf8Converter::decode(input_iter)),
-                }
-            },
+        if self.columns {
+            return write_column_names(input, &mut writer);
+        }
+        let encoding_options = self.encoding_options();
+        convert(
+            self.input_type(),
+            &encoding_options,
+            &self.decoding_options(),
+            input,
             &mut writer,
-        )
+        )?;
+        utils::print_encoding_stats(None, &encoding_options.stats);
+        Ok(())
+    }
+
+    pub(crate) fn append_to_file(&self, input: &[u8], path: &Path) -> std::io::Result<()> {
+        // A missing or empty file is started with the given options
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let encoding_options = if file.metadata()?.len() == 0 {
+            self.encoding_options()
+        } else {
+            EncodingOptions {
+                jobs: self.block_jobs(),
+                ..Utf8Converter::appending_options(&mut file)?
+            }
+        };
+        Utf8Converter::append(&mut file, &encoding_options, input)?;
+        utils::print_encoding_stats(None, &encoding_options.stats);
+        Ok(())
+    }
+
+    pub(crate) fn convert_file(
+        &self,
+        path: &Path,
+        mut writer: Box<dyn Write>,
+    ) -> std::io::Result<()> {
+        match &self.rows {
+            Some(rows) => Utf8Converter::decode_rows(
+                &mut File::options().read(true).open(path)?,
+                rows.0.clone(),
+                &mut writer,
+            ),
+            None => self.write_to_output(File::open(path)?, writer),
+        }
     }
 }
+
+pub(crate) fn convert(
+    input_type: &FormatType,
+    encoding_options: &EncodingOptions,
+    decoding_options: &DecodingOptions,
+    mut input: impl Read,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    // Inputs fitting in memory are converted as a whole, larger ones as they are read : the encoder
+    // only keeps the input that the codebook is fitted to, the decoder the streams without blocks.
+    // What was converted before an error is still written.
+    let mut head = Vec::new();
+    input
+        .by_ref()
+        .take(IN_MEMORY_LEN + 1)
+        .read_to_end(&mut head)?;
+    if head.len() as u64 <= IN_MEMORY_LEN {
+        let mut output = Vec::new();
+        let res = match input_type {
+            FormatType::Csv => {
+                Utf8Converter::encode_into_with_options(encoding_options, &head, &mut output)
+            }
+            FormatType::Bncsv => {
+                Utf8Converter::decode_into_with_options(decoding_options, &head, &mut output)
+            }
+        };
+        writer.write_all(&output)?;
+        return res;
+    }
+    let input = Cursor::new(head).chain(input);
+    match input_type {
+        FormatType::Csv => {
+            let mut read_error = None;
+            let written = Utf8Converter::encode_with_options(
+                encoding_options,
+                read_bytes(input, &mut read_error),
+            )
+            .try_chunks(OUTPUT_CHUNK_LEN)
+            .try_for_each(|chunk| writer.write_all(&chunk?));
+            match read_error {
+                Some(e) => Err(e),
+                None => written,
+            }
+        }
+        FormatType::Bncsv => {
+            io::copy(
+                &mut BncsvDecoder::with_options(decoding_options, input),
+                writer,
+            )?;
+            Ok(())
+        }
+    }
+}
+
+fn read_bytes<'a>(
+    input: impl Read + 'a,
+    read_error: &'a mut Option<io::Error>,
+) -> impl Iterator<Item = u8> + 'a {
+    // Bytes of the input up to its first error, which is kept to be reported by the caller
+    BufReader::new(input).bytes().map_while(move |b| match b {
+        Ok(b) => Some(b),
+        Err(e) => {
+            *read_error = Some(e);
+            None
+        }
+    })
+}
+
+fn write_column_names(input: impl Read, writer: &mut Box<dyn Write>) -> std::io::Result<()> {
+    // One column name per line, only the start of the stream is read
+    let mut read_error = None;
+    let metadata = Utf8Converter::read_metadata(read_bytes(input, &mut read_error));
+    if let Some(e) = read_error {
+        return Err(e);
+    }
+    let column_names = metadata?.column_names().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "The bncsv file has no header row",
+        )
+    })?;
+    column_names
+        .iter()
+        .try_for_each(|name| writeln!(writer, "{}", name))
+}
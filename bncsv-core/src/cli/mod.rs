@@ -7,8 +7,15 @@ use argh::FromArgs;
 use glob::GlobError;
 use std::fs::File;
 use std::io::Write;
+use std::sync::Arc;
 use std::{io::Read, path::PathBuf};
-use utils::{print_file_result, FormatType};
+use utils::{print_file_result, Delimiter, FormatType, RowRange};
+
+use bncsv_core::fmt::columnar::ColumnSelection;
+use bncsv_core::fmt::header::Codebook;
+use bncsv_core::fmt::line_endings::LineEndings;
+use bncsv_core::fmt::metadata::HeaderRow;
+use bncsv_core::fmt::utf8::{DecodingOptions, EncodingOptions};
 
 #[derive(FromArgs)]
 #[argh(description = "BNCSV Format CLI Tool")]
@@ -19,9 +26,9 @@ pub struct Cli {
     #[argh(
         option,
         short = 'i',
-        description = "type of input file : ['csv', 'bncsv']"
+        description = "type of input file, required without subcommand : ['csv', 'bncsv']"
     )]
-    pub input_type: FormatType,
+    pub input_type: Option<FormatType>,
 
     #[argh(option, short = 'o', description = "output path dir")]
     pub output: Option<PathBuf>,
@@ -34,6 +41,143 @@ pub struct Cli {
 
     #[argh(option, short = 'j', description = "number of jobs to run in parallel")]
     pub jobs: Option<usize>,
+
+    #[argh(
+        option,
+        from_str_fn(utils::parse_codebook),
+        short = 'c',
+        description = "codebook used when encoding : ['static', 'adaptive', 'columns', 'range', 'context']"
+    )]
+    pub codebook: Option<Codebook>,
+
+    #[argh(
+        option,
+        short = 'b',
+        description = "encode in independent blocks of at least this many bytes, decoded in parallel"
+    )]
+    pub block_size: Option<usize>,
+
+    #[argh(
+        option,
+        description = "when encoding, write a row index with an entry every this many rows"
+    )]
+    pub index_interval: Option<u64>,
+
+    #[argh(
+        option,
+        description = "when decoding a single file, only output the rows <start>..<end>"
+    )]
+    pub rows: Option<RowRange>,
+
+    #[argh(
+        switch,
+        description = "when encoding, do not write the checksums used to detect corrupted data"
+    )]
+    pub no_checksum: bool,
+
+    #[argh(
+        switch,
+        description = "when encoding, convert CRLF and CR line endings to LF instead of preserving them"
+    )]
+    pub normalize_line_endings: bool,
+
+    #[argh(
+        option,
+        short = 'd',
+        description = "field delimiter used when encoding : [',', ';', 'tab', '|']"
+    )]
+    pub delimiter: Option<Delimiter>,
+
+    #[argh(
+        switch,
+        description = "when encoding, read numbers with a decimal comma instead of a decimal point"
+    )]
+    pub decimal_comma: bool,
+
+    #[argh(
+        option,
+        from_str_fn(utils::parse_header_row),
+        description = "whether the first line holds the column names when encoding : ['detect', 'present', 'absent']"
+    )]
+    pub header_row: Option<HeaderRow>,
+
+    #[argh(
+        switch,
+        description = "when decoding, only output the column names of the header row"
+    )]
+    pub columns: bool,
+
+    #[argh(
+        switch,
+        description = "when encoding, compress the values of each column in their own stream"
+    )]
+    pub columnar: bool,
+
+    #[argh(
+        option,
+        from_str_fn(utils::parse_column_selection),
+        description = "when encoding, store the differences between consecutive values of these columns : ['auto', '<index>,<index>...']"
+    )]
+    pub delta: Option<ColumnSelection>,
+
+    #[argh(
+        option,
+        from_str_fn(utils::parse_column_selection),
+        description = "when encoding, store the floats of these columns XORed with the previous one : ['auto', '<index>,<index>...']"
+    )]
+    pub xor_floats: Option<ColumnSelection>,
+
+    #[argh(
+        option,
+        from_str_fn(utils::parse_column_selection),
+        description = "when encoding, store the numbers of these columns as integers scaled by their fixed number of decimals : ['auto', '<index>,<index>...']"
+    )]
+    pub scaled: Option<ColumnSelection>,
+
+    #[argh(
+        switch,
+        description = "when encoding, learn multi-byte tokens such as '0.' from the input and give them their own codes"
+    )]
+    pub tokens: bool,
+
+    #[argh(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum Command {
+    Append(AppendCommand),
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "append",
+    description = "add csv rows to the end of a bncsv file, encoded with the options of the file"
+)]
+pub struct AppendCommand {
+    #[argh(
+        positional,
+        description = "bncsv file, created with the encoding options given before 'append' when missing"
+    )]
+    pub file: PathBuf,
+
+    #[argh(positional, description = "csv file holding the rows")]
+    pub input: Option<PathBuf>,
+}
+
+impl AppendCommand {
+    pub(crate) fn run(&self, cli: &Cli) -> std::io::Result<()> {
+        // The rows are read and encoded with the options given before the subcommand,
+        // the options of an existing file take precedence
+        self.validate_input(cli)?;
+        let input = match &self.input {
+            Some(path) => std::fs::read(path)?,
+            None => read_stdin()?,
+        };
+        cli.append_to_file(&input, &self.file)
+    }
 }
 
 impl Cli {
@@ -76,7 +220,53 @@ impl Cli {
         })
     }
 
+    fn input_type(&self) -> &FormatType {
+        // Checked by `validate_input_type` before any conversion, appended rows are csv
+        self.input_type.as_ref().unwrap_or(&FormatType::Csv)
+    }
+
+    fn block_jobs(&self) -> usize {
+        // Number of threads working on the blocks of a single file
+        self.jobs
+            .map(|x| x.max(1))
+            .unwrap_or(std::thread::available_parallelism().map_or(1, |x| x.get()))
+    }
+
+    pub(crate) fn encoding_options(&self) -> EncodingOptions {
+        EncodingOptions {
+            codebook: self.codebook.unwrap_or(Codebook::Utf8Static),
+            block_size: self.block_size,
+            jobs: self.block_jobs(),
+            index_interval: self.index_interval,
+            checksum: !self.no_checksum,
+            line_endings: if self.normalize_line_endings {
+                LineEndings::Normalize
+            } else {
+                LineEndings::Preserve
+            },
+            columnar: self.columnar,
+            delta: self.delta.clone().unwrap_or_default(),
+            xor_floats: self.xor_floats.clone().unwrap_or_default(),
+            scaled: self.scaled.clone().unwrap_or_default(),
+            tokens: self.tokens,
+            header_row: self.header_row.unwrap_or_default(),
+            delimiter: self.delimiter.as_ref().map_or(b',', |d| d.0),
+            decimal_mark: if self.decimal_comma { b',' } else { b'.' },
+            stats: Arc::default(),
+        }
+    }
+
+    pub(crate) fn decoding_options(&self) -> DecodingOptions {
+        DecodingOptions {
+            jobs: self.block_jobs(),
+        }
+    }
+
     pub(crate) fn entrypoint(&self) -> std::io::Result<()> {
+        if let Some(Command::Append(append)) = &self.command {
+            return append.run(self);
+        }
+        self.validate_input_type()?;
         if !self.pipe && self.paths.len() == 0 {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
@@ -95,10 +285,12 @@ impl Cli {
             .collect::<Result<Vec<Vec<PathBuf>>, GlobError>>()
             .map(|x| x.into_iter().flatten().collect::<Vec<PathBuf>>());
         let n_files = input_paths.as_ref().map(|res| res.len()).unwrap_or(0); //Any error results in a len of zero
+        self.validate_rows_range(n_files)?;
+        self.validate_columns()?;
         match (n_files, self.pipe) {
             // (n_files, self.pipe) are partially redundant but this way is supposed to be more readable
             (0, true) => self.write_to_output(
-                Box::new(std::io::stdin().bytes()),
+                std::io::stdin().lock(),
                 if self.output.is_none() {
                     Box::new(std::io::stdout())
                 } else {
@@ -106,6 +298,7 @@ impl Cli {
                         File::options()
                             .write(true)
                             .create(true)
+                            .truncate(true)
                             .open(&self.output.as_ref().unwrap())?,
                     )
                 },
@@ -115,14 +308,18 @@ impl Cli {
                 match &self.output {
                     Some(ref out_p) if !out_p.is_dir() => {
                         print_file_result(
-                            &self.input_type,
+                            self.input_type(),
                             p,
                             &out_p,
                             {
-                                self.write_to_output(
-                                    Box::new(File::options().read(true).open(p)?.bytes()),
+                                self.convert_file(
+                                    p,
                                     Box::new(
-                                        File::options().write(true).create(true).open(&out_p)?,
+                                        File::options()
+                                            .write(true)
+                                            .create(true)
+                                            .truncate(true)
+                                            .open(&out_p)?,
                                     ),
                                 )
                                 .is_ok()
@@ -131,10 +328,7 @@ impl Cli {
                         );
                     }
                     None => {
-                        self.write_to_output(
-                            Box::new(File::options().read(true).open(p)?.bytes()),
-                            Box::new(std::io::stdout()),
-                        )?;
+                        self.convert_file(p, Box::new(std::io::stdout()))?;
                         std::io::stdout().flush()?;
                     }
                     _ => {
@@ -173,3 +367,10 @@ impl Cli {
         Ok(())
     }
 }
+
+fn read_stdin() -> std::io::Result<Vec<u8>> {
+    // The piped rows are appended once fully read
+    let mut input = Vec::new();
+    std::io::stdin().read_to_end(&mut input)?;
+    Ok(input)
+}
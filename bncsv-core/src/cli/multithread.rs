@@ -3,14 +3,13 @@ use std::sync::mpsc::Receiver;
 use std::sync::mpsc::Sender;
 use std::{
     fs::{self, File},
-    io::{Read, Write},
     sync::{mpsc, Arc},
     thread::{self, JoinHandle},
 };
 
-use bncsv_core::{compr::BnCsvConverter, fmt::utf8::Utf8Converter};
+use bncsv_core::fmt::utf8::{DecodingOptions, EncodingOptions};
 
-use super::Cli;
+use super::{io::convert, Cli};
 use crate::cli::utils;
 use glob::GlobError;
 use std::io;
@@ -51,7 +50,7 @@ impl Cli {
                     }
                 }
                 .with_extension({
-                    match self.input_type {
+                    match self.input_type() {
                         FormatType::Csv => "bncsv",
                         FormatType::Bncsv => "csv",
                     }
@@ -77,43 +76,44 @@ impl Cli {
         tasks.into_iter().enumerate().for_each(|(i, query)| {
             channels[i % n_threads].0.send(query).unwrap();
         });
-        let input_format = Arc::new(self.input_type.clone());
+        let input_format = Arc::new(self.input_type().clone());
+        // Files are already spread over the threads, their blocks are encoded sequentially
+        let encoding_options = Arc::new(EncodingOptions {
+            jobs: 1,
+            ..self.encoding_options()
+        });
 
         // Run consummers
         let handles = channels
             .into_iter()
             .map(|(_, rx)| {
                 let f = input_format.clone();
+                let options = encoding_options.clone();
                 thread::spawn(move || {
                     while let Ok(data) = rx.recv() {
-                        let input_bytes =
-                            File::options().read(true).open(&data.input_path)?.bytes();
+                        // Every file gets its own statistics
+                        let options = EncodingOptions {
+                            stats: Arc::default(),
+                            ..options.as_ref().clone()
+                        };
+                        let input = File::open(&data.input_path)?;
                         if let Some(p) = &data.output_path.parent() {
                             if !p.exists() {
                                 fs::create_dir_all(&p)?;
                             }
                         }
 
-                        let res = utils::consume_iter_in_writer(
-                            {
-                                match f.as_ref() {
-                                    FormatType::Csv => Box::new(Utf8Converter::encode(
-                                        input_bytes
-                                            .map(|x| x.expect("Could not read input utf-8 byte")),
-                                    ))
-                                        as Box<dyn Iterator<Item = std::io::Result<u8>>>,
-                                    FormatType::Bncsv => Box::new(Utf8Converter::decode(
-                                        input_bytes
-                                            .map(|x| x.expect("Could not read input bncsv byte")),
-                                    )),
-                                }
-                            },
-                            &mut (Box::new(
-                                File::options()
-                                    .write(true)
-                                    .create(true)
-                                    .open(&data.output_path)?,
-                            ) as Box<dyn Write>),
+                        let mut writer = File::options()
+                            .write(true)
+                            .create(true)
+                            .truncate(true)
+                            .open(&data.output_path)?;
+                        let res = convert(
+                            &f,
+                            &options,
+                            &DecodingOptions::default(),
+                            input,
+                            &mut writer,
                         );
 
                         print_file_result(
@@ -123,6 +123,7 @@ impl Cli {
                             res.is_ok(),
                             false,
                         );
+                        utils::print_encoding_stats(Some(&data.input_path), &options.stats);
                     }
                     Ok(())
                 })
@@ -1,8 +1,12 @@
 use argh::FromArgValue;
 
-use crate::utils::iterators::TryChunks;
+use bncsv_core::fmt::columnar::ColumnSelection;
+use bncsv_core::fmt::header::Codebook;
+use bncsv_core::fmt::metadata::HeaderRow;
+use bncsv_core::fmt::utf8::EncodingStats;
+use std::ops::Range;
 use std::path::Path;
-use std::{io::Write, path::PathBuf};
+use std::path::PathBuf;
 #[allow(non_upper_case_globals)]
 pub const style_bold: &str = "\x1B[1m";
 #[allow(non_upper_case_globals)]
@@ -35,25 +39,70 @@ impl FromArgValue for FormatType {
         }
     }
 }
-pub(crate) fn consume_iter_in_writer(
-    iter: impl Iterator<Item = std::io::Result<u8>>,
-    writer: &mut Box<dyn Write>,
-) -> Result<(), std::io::Error> {
-    iter.try_chunks(2048).try_for_each(|x| {
-        match x {
-            Ok(x) => {
-                writer
-                    .write_all(&x)
-                    .expect("Failed to write the output -> ");
-            }
-            Err(e) => {
-                return Err(e);
-            }
+pub fn parse_codebook(value: &str) -> Result<Codebook, String> {
+    match value.to_lowercase().as_str() {
+        "static" => Ok(Codebook::Utf8Static),
+        "adaptive" => Ok(Codebook::Adaptive),
+        "columns" => Ok(Codebook::PerColumn),
+        "range" => Ok(Codebook::Range),
+        "context" => Ok(Codebook::Context),
+        _ => Err("Not implemented codebook".to_string()),
+    }
+}
+pub fn parse_header_row(value: &str) -> Result<HeaderRow, String> {
+    match value.to_lowercase().as_str() {
+        "detect" => Ok(HeaderRow::Detect),
+        "present" => Ok(HeaderRow::Present),
+        "absent" => Ok(HeaderRow::Absent),
+        _ => Err("Not implemented header row mode".to_string()),
+    }
+}
+pub fn parse_column_selection(value: &str) -> Result<ColumnSelection, String> {
+    // `auto` or the comma-separated indexes of the columns, starting at 0
+    match value.to_lowercase().as_str() {
+        "auto" => Ok(ColumnSelection::Auto),
+        indexes => indexes
+            .split(',')
+            .map(|i| i.trim().parse::<usize>())
+            .collect::<Result<Vec<usize>, _>>()
+            .map(ColumnSelection::Columns)
+            .map_err(|_| "Expected 'auto' or column indexes such as 0,2".to_string()),
+    }
+}
+#[derive(Clone)]
+pub struct Delimiter(pub u8);
+
+impl FromArgValue for Delimiter {
+    fn from_arg_value(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "," | "comma" => Ok(Delimiter(b',')),
+            ";" | "semicolon" => Ok(Delimiter(b';')),
+            "\t" | "tab" => Ok(Delimiter(b'\t')),
+            "|" | "pipe" => Ok(Delimiter(b'|')),
+            _ => Err("Not implemented delimiter".to_string()),
         }
-        Ok(())
-    })
+    }
 }
+pub struct RowRange(pub Range<u64>);
 
+impl FromArgValue for RowRange {
+    fn from_arg_value(value: &str) -> Result<Self, String> {
+        // <start>..<end>, the end being optional
+        let (start, end) = value
+            .split_once("..")
+            .ok_or_else(|| "Row range must be written <start>..<end>".to_string())?;
+        let start = start
+            .parse::<u64>()
+            .map_err(|e| format!("Invalid row range start : {}", e))?;
+        let end = match end {
+            "" => u64::MAX,
+            end => end
+                .parse::<u64>()
+                .map_err(|e| format!("Invalid row range end : {}", e))?,
+        };
+        Ok(RowRange(start..end))
+    }
+}
 fn shorten_path(path: &PathBuf) -> String {
     let path_str = path.to_string_lossy();
     if path_str.len() > 50 {
@@ -92,3 +141,24 @@ pub(crate) fn print_file_result(
         }
     }
 }
+
+pub(crate) fn print_encoding_stats(path: Option<&Path>, stats: &EncodingStats) {
+    // Same format as `print_file_result`, written to stderr as stdout may hold the encoded data
+    let location = path
+        .map(|p| format!(" in {}", shorten_path(&p.to_path_buf())))
+        .unwrap_or_default();
+    let normalized = stats.normalized_line_endings();
+    if normalized > 0 {
+        eprintln!("{style_bold} [CSV->BNCSV] Normalised {} line endings to LF{}{style_unbold}",
+            normalized,
+            location,
+        );
+    }
+    let escaped = stats.escaped_bytes();
+    if escaped > 0 {
+        eprintln!("{style_bold} [CSV->BNCSV] Escaped {} bytes missing from the codebook{}{style_unbold}",
+            escaped,
+            location,
+        );
+    }
+}
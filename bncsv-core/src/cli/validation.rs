@@ -1,5 +1,7 @@
 use crate::Cli;
 
+use super::{AppendCommand, FormatType};
+
 #[cfg(feature = "multithreading")]
 use super::multithread::TaskQuery;
 
@@ -24,4 +26,70 @@ impl Cli {
         }
         Ok(())
     }
+
+    pub(crate) fn validate_columns(&self) -> Result<(), std::io::Error> {
+        if !self.columns {
+            return Ok(());
+        }
+        if self.input_type() != &FormatType::Bncsv || self.rows.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Column names can only be read from bncsv files, without row range",
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn validate_input_type(&self) -> Result<(), std::io::Error> {
+        if self.input_type.is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "The type of input file is required : -i <input-type>",
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn validate_rows_range(&self, n_files: usize) -> Result<(), std::io::Error> {
+        if self.rows.is_none() {
+            return Ok(());
+        }
+        if self.input_type() != &FormatType::Bncsv {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Row ranges can only be used when decoding bncsv files",
+            ));
+        }
+        if self.pipe || n_files != 1 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Row ranges require a single input file",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl AppendCommand {
+    pub(crate) fn validate_input(&self, cli: &Cli) -> Result<(), std::io::Error> {
+        if cli.pipe == self.input.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Appending rows requires a single input file",
+            ));
+        }
+        if !cli.paths.is_empty()
+            || cli.input_type.is_some()
+            || cli.output.is_some()
+            || cli.abs_pathbase.is_some()
+            || cli.rows.is_some()
+            || cli.columns
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Appending rows only takes the encoding options, before 'append'",
+            ));
+        }
+        Ok(())
+    }
 }
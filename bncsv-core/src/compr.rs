@@ -1,154 +1,752 @@
-use std::{io, iter};
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{BinaryHeap, HashSet, VecDeque},
+    io, iter,
+};
 
-use crate::utils::iterators::TryChunks;
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+use crate::utils::bits::{BitReader, BitWriter};
+use crate::utils::iterators::Either;
+
+// Prefix code of a symbol : `len` bits right-aligned in `value`, the first bit being the most significant one
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug)]
+pub struct Code {
+    pub value: u32,
+    pub len: u8,
+}
+impl Code {
+    pub const fn from_bits(bits: &[u8]) -> Code {
+        // Allows hard-coded codes to be written bit by bit inside const symbols
+        let mut value = 0;
+        let mut i = 0;
+        while i < bits.len() {
+            value = value << 1 | bits[i] as u32;
+            i += 1;
+        }
+        Code {
+            value,
+            len: bits.len() as u8,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn escaped(self, b: u8) -> Code {
+        // Escape code followed by the raw bits of the byte
+        Code {
+            value: self.value << ESCAPED_BYTE_BITS | u32::from(b),
+            len: self.len + ESCAPED_BYTE_BITS as u8,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
 pub struct Symbol {
-    pub bits: &'static [u8],
-    pub value: u8,
+    pub code: Code,
+    // Decoded bytes, several for multi-byte symbols such as `NaN`
+    pub value: Cow<'static, [u8]>,
+}
+impl Symbol {
+    pub fn escaped_byte(b: u8) -> Symbol {
+        // Byte read after the escape symbol
+        Symbol {
+            code: Code::default(),
+            value: Cow::Borrowed(std::slice::from_ref(&BYTE_VALUES[usize::from(b)])),
+        }
+    }
 }
+// Code of each byte of a codebook, none for the bytes missing from it
+pub type LookupTable = [Option<Code>; 256];
+const fn byte_values() -> [u8; 256] {
+    let mut values = [0_u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        values[i] = i as u8;
+        i += 1;
+    }
+    values
+}
+// Every byte value, lets single-byte symbol values be borrowed
+pub static BYTE_VALUES: [u8; 256] = byte_values();
+// Bits following the escape symbol, the escaped byte is written as is
+pub const ESCAPED_BYTE_BITS: usize = 8;
+// Number of codes packed before their bytes are yielded
+const PACKING_BATCH: usize = 4096;
+
 pub trait BnCsvConverter {
     fn encode(raw_data: impl IntoIterator<Item = u8>) -> impl Iterator<Item = std::io::Result<u8>>;
     fn decode(data: impl IntoIterator<Item = u8>) -> impl Iterator<Item = std::io::Result<u8>>;
+    fn encode_into(raw_data: &[u8], out: &mut Vec<u8>) -> std::io::Result<()>;
+    fn decode_into(data: &[u8], out: &mut Vec<u8>) -> std::io::Result<()>;
+
+    fn encode_slice(raw_data: &[u8]) -> std::io::Result<Vec<u8>> {
+        // Whole buffer counterpart of `encode`, without a `Result` per byte.
+        // The buffer still comes as an `io::Result`, `encode_into` sharing the encoding of the options that
+        // can be refused (a column encoding on a missing column, tokens without a fitted codebook...).
+        let mut out = Vec::new();
+        Self::encode_into(raw_data, &mut out)?;
+        Ok(out)
+    }
+
+    fn decode_slice(data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        Self::decode_into(data, &mut out)?;
+        Ok(out)
+    }
 }
 
-pub struct DecoderUnfold<I: Iterator<Item = u8>> {
-    iter: I,
-    decoding_tree: DecodingTree,
-    eoc_symbol: &'static Symbol,
+pub enum Token<'a> {
+    // Single input byte, encoded through the lookup table
+    Byte(u8),
+    // Multi-byte symbol matched in the input
+    Symbol(&'a Symbol),
 }
-impl<I: Iterator<Item = u8>> DecoderUnfold<I> {
-    pub fn new(iter: I, decoding_tree: DecodingTree, eoc_symbol: &'static Symbol) -> Self {
-        DecoderUnfold {
-            iter,
-            decoding_tree,
-            eoc_symbol,
+
+impl Token<'_> {
+    pub fn code(&self, lookup_table: &LookupTable) -> Option<Code> {
+        match self {
+            Token::Byte(b) => lookup_table[usize::from(*b)],
+            Token::Symbol(symb) => Some(symb.code),
+        }
+    }
+
+    pub fn value(&self) -> &[u8] {
+        match self {
+            Token::Byte(b) => std::slice::from_ref(b),
+            Token::Symbol(symb) => &symb.value,
         }
     }
 }
 
-impl<I: Iterator<Item = u8>> Iterator for DecoderUnfold<I>
-where
-    I: Iterator<Item = u8>,
-{
-    type Item = std::io::Result<Symbol>;
+pub struct Tokenizer<'a, I: Iterator<Item = u8>> {
+    iter: I,
+    // Multi-byte symbols, the first one matching the input wins
+    tokens: &'a [Symbol],
+    // Bytes starting a token, the other ones are yielded without lookahead
+    starts: [bool; 256],
+    lookahead: VecDeque<u8>,
+    max_len: usize,
+}
+
+impl<'a, I: Iterator<Item = u8>> Iterator for Tokenizer<'a, I> {
+    type Item = Token<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut loc_target = Box::new(&self.decoding_tree);
-        for bit in self.iter.by_ref() {
-            if bit == 0 && loc_target.down.is_some() {
-                *loc_target = loc_target.down.as_ref().unwrap();
-            } else if bit == 1 && loc_target.up.is_some() {
-                *loc_target = loc_target.up.as_ref().unwrap();
+        let first = match self.lookahead.front() {
+            Some(&b) => b,
+            None => {
+                let b = self.iter.next()?;
+                if !self.starts[usize::from(b)] {
+                    return Some(Token::Byte(b));
+                }
+                self.lookahead.push_back(b);
+                b
+            }
+        };
+        while self.lookahead.len() < self.max_len {
+            match self.iter.next() {
+                Some(b) => self.lookahead.push_back(b),
+                None => break,
+            }
+        }
+        let lookahead = &self.lookahead;
+        match self
+            .tokens
+            .iter()
+            .find(|t| lookahead.iter().take(t.value.len()).eq(t.value.iter()))
+        {
+            Some(token) => {
+                self.lookahead.drain(..token.value.len());
+                Some(Token::Symbol(token))
+            }
+            None => {
+                self.lookahead.pop_front();
+                Some(Token::Byte(first))
+            }
+        }
+    }
+}
+
+pub fn tokenize<I: IntoIterator<Item = u8>>(
+    data: I,
+    tokens: &[Symbol],
+) -> Tokenizer<'_, I::IntoIter> {
+    // Splits the input in symbols, matching the multi-byte tokens greedily.
+    // The tokens must be ordered so that a token comes before the tokens it starts with.
+    let mut starts = [false; 256];
+    tokens
+        .iter()
+        .filter_map(|t| t.value.first())
+        .for_each(|&b| starts[usize::from(b)] = true);
+    Tokenizer {
+        iter: data.into_iter(),
+        tokens,
+        starts,
+        lookahead: VecDeque::new(),
+        max_len: tokens.iter().map(|t| t.value.len()).max().unwrap_or(1),
+    }
+}
+
+// Bits peeked by a lookup in a decoding table, longer codes are resolved by a subtable
+const TABLE_BITS: u8 = 10;
+// Symbols resolved by a single lookup at most
+const LOOKUP_SYMBOLS: usize = 3;
+
+#[derive(Clone, Copy, Debug)]
+enum Lookup {
+    // Leaves of the codes starting the peeked bits, with the number of bits read once each of them is decoded.
+    // Only the leaves within the bits read so far are decoded, the bits peeked past them being zeros.
+    Leaves {
+        leaves: [u16; LOOKUP_SYMBOLS],
+        ends: [u8; LOOKUP_SYMBOLS],
+        count: u8,
+    },
+    // Codes longer than the peeked bits, the subtable starting at this offset resolves their remaining bits
+    Subtable(usize),
+    // No code starts with the first `n` bits, the last of them being the invalid one
+    Invalid(u8),
+}
+impl Lookup {
+    fn leaf(leaf: usize, len: u8) -> Lookup {
+        let (mut leaves, mut ends) = ([0; LOOKUP_SYMBOLS], [0; LOOKUP_SYMBOLS]);
+        leaves[0] = leaf as u16;
+        ends[0] = len;
+        Lookup::Leaves {
+            leaves,
+            ends,
+            count: 1,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Leaf {
+    symbol: Symbol,
+    // Context of the symbol following this one
+    context: usize,
+    // Decoded byte of the symbols with a single one, staying in the same context and neither EOC nor escape
+    byte: Option<u8>,
+}
+
+#[derive(Debug)]
+pub struct DecodingTable {
+    // Symbols of a context along with their EOC and escape symbols, resolved by looking up the next bits
+    leaves: Vec<Leaf>,
+    eoc: Option<usize>,
+    esc: Option<usize>,
+    lookups: Vec<Lookup>,
+    // Bits indexing the subtables, after the first TABLE_BITS bits
+    sub_bits: u8,
+}
+
+impl DecodingTable {
+    pub fn new(
+        symbols: &[Symbol],
+        eoc_symbol: &Symbol,
+        esc_symbol: Option<&Symbol>,
+    ) -> DecodingTable {
+        let symbols = symbols
+            .iter()
+            .map(|s| (s.clone(), 0))
+            .collect::<Vec<(Symbol, usize)>>();
+        DecodingTable::with_contexts(0, &symbols, eoc_symbol, esc_symbol)
+    }
+
+    pub fn with_contexts(
+        context: usize,
+        symbols: &[(Symbol, usize)],
+        eoc_symbol: &Symbol,
+        esc_symbol: Option<&Symbol>,
+    ) -> DecodingTable {
+        // Codes of the given context, each symbol being given the context of the next symbol.
+        // A lookup resolves several symbols as long as they stay in this context.
+        let mut leaves = symbols
+            .iter()
+            .map(|(symbol, context)| Leaf {
+                symbol: symbol.clone(),
+                context: *context,
+                byte: None,
+            })
+            .collect::<Vec<Leaf>>();
+        // Leaves of a prefix code have distinct codes, comparing them is enough
+        let find = |symbol: &Symbol| leaves.iter().position(|l| l.symbol.code == symbol.code);
+        let (eoc, esc) = (find(eoc_symbol), esc_symbol.and_then(find));
+        for (i, leaf) in leaves.iter_mut().enumerate() {
+            if let ([b], false) = (&*leaf.symbol.value, [eoc, esc].contains(&Some(i))) {
+                leaf.byte = Some(*b).filter(|_| leaf.context == context);
+            }
+        }
+        let max_len = leaves.iter().map(|l| l.symbol.code.len).max().unwrap_or(0);
+        let sub_bits = max_len.saturating_sub(TABLE_BITS);
+        let prefixes = leaves
+            .iter()
+            .flat_map(|l| {
+                let Code { value, len } = l.symbol.code;
+                (1..=len).map(move |n| (n, value >> (len - n)))
+            })
+            .collect::<HashSet<(u8, u32)>>();
+        let invalid = |len: u8, bits: u32| {
+            Lookup::Invalid(
+                (1..=len)
+                    .find(|&n| !prefixes.contains(&(n, bits >> (len - n))))
+                    .unwrap_or(len),
+            )
+        };
+
+        let mut lookups = (0..1 << TABLE_BITS)
+            .map(|bits| invalid(TABLE_BITS, bits))
+            .collect::<Vec<Lookup>>();
+        for (i, leaf) in leaves.iter().enumerate() {
+            let Code { value, len } = leaf.symbol.code;
+            let (start, filled) = if len <= TABLE_BITS {
+                ((value << (TABLE_BITS - len)) as usize, TABLE_BITS - len)
             } else {
-                return Some(Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!("Invalid character {} encountered in the input data", bit),
-                )));
+                let prefix = (value >> (len - TABLE_BITS)) as usize;
+                let offset = match lookups[prefix] {
+                    Lookup::Subtable(offset) => offset,
+                    _ => {
+                        let offset = lookups.len();
+                        lookups[prefix] = Lookup::Subtable(offset);
+                        lookups.extend(
+                            (0..1 << sub_bits)
+                                .map(|bits| invalid(max_len, (prefix as u32) << sub_bits | bits)),
+                        );
+                        offset
+                    }
+                };
+                let rest = len - TABLE_BITS;
+                let rest_value = value & ((1 << rest) - 1);
+                (
+                    offset + (rest_value << (sub_bits - rest)) as usize,
+                    sub_bits - rest,
+                )
+            };
+            lookups[start..start + (1 << filled)].fill(Lookup::leaf(i, len));
+        }
+
+        // The peeked bits left after a code may hold the next ones
+        let single = lookups[..1 << TABLE_BITS].to_vec();
+        for bits in 0..1_usize << TABLE_BITS {
+            while let Lookup::Leaves {
+                leaves: ids,
+                ends,
+                count,
+            } = &mut lookups[bits]
+            {
+                let n = usize::from(*count);
+                let (last, end) = (usize::from(ids[n - 1]), ends[n - 1]);
+                if n == LOOKUP_SYMBOLS
+                    || [eoc, esc].contains(&Some(last))
+                    || leaves[last].context != context
+                {
+                    break;
+                }
+                match single[bits << end & ((1 << TABLE_BITS) - 1)] {
+                    Lookup::Leaves {
+                        leaves: next,
+                        ends: next_ends,
+                        ..
+                    } if next_ends[0] <= TABLE_BITS - end => {
+                        ids[n] = next[0];
+                        ends[n] = end + next_ends[0];
+                        *count += 1;
+                    }
+                    _ => break,
+                }
             }
+        }
+        DecodingTable {
+            leaves,
+            eoc,
+            esc,
+            lookups,
+            sub_bits,
+        }
+    }
 
-            match loc_target.root {
-                Some(symb) if symb == *self.eoc_symbol => {
-                    return None;
+    fn lookup(
+        &self,
+        bits: &mut BitReader<impl Iterator<Item = u8>>,
+    ) -> Option<io::Result<([u16; LOOKUP_SYMBOLS], usize)>> {
+        // Leaves of the next codes, the input bytes being read one at a time until a code is complete.
+        // Nothing is read past the last byte of a code, besides the lookahead of the reader.
+        bits.read_ahead();
+        loop {
+            let mut lookup = self.lookups[bits.peek(TABLE_BITS) as usize];
+            if let Lookup::Subtable(offset) = lookup {
+                let rest = bits.peek(TABLE_BITS + self.sub_bits) & ((1 << self.sub_bits) - 1);
+                lookup = self.lookups[offset + rest as usize];
+            }
+            let available = bits.available();
+            match lookup {
+                Lookup::Leaves {
+                    leaves,
+                    ends,
+                    count,
+                } => {
+                    let count = ends[..usize::from(count)]
+                        .iter()
+                        .take_while(|&&end| u32::from(end) <= available)
+                        .count();
+                    if count > 0 {
+                        bits.consume(ends[count - 1]);
+                        return Some(Ok((leaves, count)));
+                    }
                 }
-                Some(symb) => {
-                    return Some(Ok(symb));
+                Lookup::Invalid(len) if u32::from(len) <= available => {
+                    return Some(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Invalid character {} encountered in the input data",
+                            bits.peek(len) & 1
+                        ),
+                    )));
                 }
-                None => {}
+                _ => {}
+            }
+            if !bits.read_byte() {
+                return None;
             }
         }
-        None
     }
 }
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub struct DecodingTree {
-    pub root: Option<Symbol>,
-    pub up: Option<Box<DecodingTree>>,
-    pub down: Option<Box<DecodingTree>>,
+
+pub struct DecoderUnfold<I: Iterator<Item = u8>> {
+    iter: BitReader<I>,
+    // Codes of each context, the leaf of the last decoded symbol gives the context of the next one
+    tables: Vec<DecodingTable>,
+    context: usize,
+    // Leaves resolved by the last lookup, the ones from `next_pending` are not yielded yet
+    pending: [u16; LOOKUP_SYMBOLS],
+    n_pending: usize,
+    next_pending: usize,
+    // Set once the EOC symbol is read, the remaining bits are only padding
+    finished: bool,
 }
+impl<I: Iterator<Item = u8>> DecoderUnfold<I> {
+    pub fn new(iter: BitReader<I>, table: DecodingTable) -> Self {
+        // The escape symbol is followed by a raw byte, for the bytes missing from the codebook
+        DecoderUnfold::with_contexts(iter, vec![table], 0)
+    }
 
-impl DecodingTree {
-    fn init(depth: usize, symbols: Vec<Symbol>) -> DecodingTree {
-        if symbols.len() == 1 {
-            return DecodingTree {
-                root: Some(symbols[0]),
-                down: None,
-                up: None,
-            };
+    pub fn with_contexts(iter: BitReader<I>, tables: Vec<DecodingTable>, context: usize) -> Self {
+        DecoderUnfold {
+            iter,
+            tables,
+            context,
+            pending: [0; LOOKUP_SYMBOLS],
+            n_pending: 0,
+            next_pending: 0,
+            finished: false,
         }
-        let down_part: Vec<Symbol> = symbols
-            .clone()
-            .into_iter()
-            .filter(|x| x.bits.get(depth) == Some(&0_u8))
-            .collect();
-        let up_part: Vec<Symbol> = symbols
-            .into_iter()
-            .filter(|x| x.bits.get(depth) == Some(&1_u8))
-            .collect();
-        DecodingTree {
-            root: None,
-            down: {
-                if !down_part.is_empty() {
-                    Some(Box::new(DecodingTree::init(depth + 1, down_part)))
-                } else {
-                    None
+    }
+
+    pub fn decode_into(mut self, out: &mut Vec<u8>) -> std::io::Result<Vec<u8>> {
+        // Same bytes as the decoded symbols, appended to the buffer without going through `Symbol` values.
+        // The bytes read ahead of the end of the stream are given back.
+        while !self.finished {
+            let table = self
+                .tables
+                .get(self.context)
+                .ok_or_else(|| unknown_context_error(self.context))?;
+            let (leaves, count) = match table.lookup(&mut self.iter) {
+                Some(lookup) => lookup?,
+                None => return Ok(Vec::new()),
+            };
+            for &index in &leaves[..count] {
+                let index = usize::from(index);
+                let leaf = &table.leaves[index];
+                if let Some(b) = leaf.byte {
+                    out.push(b);
+                    continue;
                 }
-            },
-            up: {
-                if !up_part.is_empty() {
-                    Some(Box::new(DecodingTree::init(depth + 1, up_part)))
+                self.context = leaf.context;
+                if Some(index) == table.eoc {
+                    self.finished = true;
+                } else if Some(index) == table.esc {
+                    let b = self
+                        .iter
+                        .read(ESCAPED_BYTE_BITS as u8)
+                        .ok_or_else(truncated_escape_error)?;
+                    out.push(b as u8);
                 } else {
-                    None
+                    out.extend_from_slice(&leaf.symbol.value);
                 }
-            },
+            }
         }
+        Ok(self.iter.into_lookahead())
     }
+}
+
+fn unknown_context_error(context: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Unknown decoding context {}", context),
+    )
+}
 
-    pub fn new(symbols: &[Symbol]) -> DecodingTree {
-        DecodingTree::init(0, symbols.to_vec())
+fn truncated_escape_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Truncated escaped byte in the input data",
+    )
+}
+
+impl<I: Iterator<Item = u8>> Iterator for DecoderUnfold<I>
+where
+    I: Iterator<Item = u8>,
+{
+    type Item = std::io::Result<Symbol>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let table = match self.tables.get(self.context) {
+            Some(table) => table,
+            None => return Some(Err(unknown_context_error(self.context))),
+        };
+        if self.next_pending == self.n_pending {
+            match table.lookup(&mut self.iter)? {
+                Ok((leaves, count)) => {
+                    (self.pending, self.n_pending, self.next_pending) = (leaves, count, 0)
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        let index = usize::from(self.pending[self.next_pending]);
+        self.next_pending += 1;
+        let leaf = &table.leaves[index];
+        self.context = leaf.context;
+        if Some(index) == table.eoc {
+            self.finished = true;
+            return None;
+        }
+        if Some(index) == table.esc {
+            return Some(
+                self.iter
+                    .read(ESCAPED_BYTE_BITS as u8)
+                    .map(|b| Symbol::escaped_byte(b as u8))
+                    .ok_or_else(truncated_escape_error),
+            );
+        }
+        Some(Ok(leaf.symbol.clone()))
     }
 }
-pub fn encode<'a>(
-    lookup_table: &'static [Result<&[u8], &str>],
-    eoc_symb: &'static Symbol,
-    data: impl IntoIterator<Item = u8>,
-) -> impl Iterator<Item = std::io::Result<u8>> {
-    // Reads a interator of u8, encode them using the lookup table and return a bytes stream with the encoded data
+
+pub fn encode<'a, I: IntoIterator<Item = Token<'a>>>(
+    lookup_table: &'a LookupTable,
+    eoc_code: Code,
+    esc_code: Option<Code>,
+    data: I,
+) -> impl Iterator<Item = std::io::Result<u8>> + use<'a, I> {
+    // Reads a interator of tokens, encode them using the lookup table and return a bytes stream with the encoded data
     // and EOC symbol concatenated at the end of the stream.
+    // Bytes missing from the lookup table are written after the escape symbol, if the codebook has one.
     // The output bytes are valid-size bytes of the bits sent and ready to be saved in a file.
-    data.into_iter()
-        .flat_map(|x: u8| -> Box<dyn Iterator<Item = Result<u8, io::Error>>> {
-            match lookup_table.get(usize::from(x)) {
-                Some(Ok(y)) => Box::new(y.iter().map(|&y| Ok(y))),
-                Some(Err(_)) | None => Box::new(iter::once(Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Invalid character encountered in the input data",
-                )))),
-            }
-        })
-        .chain(eoc_symb.bits.iter().map(|&y| Ok(y)))
-        .try_chunks(8)
-        .map(|x| match x {
-            Ok(mut buff) => {
-                if buff.len() != 8 {
-                    buff.extend(iter::repeat(0_u8).take(8 - buff.len()));
+    pack_codes(
+        data.into_iter()
+            .map(move |x: Token<'a>| token_code(lookup_table, esc_code, &x))
+            .chain(iter::once(Ok(eoc_code))),
+    )
+}
+
+pub fn encode_packed<'a>(
+    lookup_table: &'a LookupTable,
+    eoc_code: Code,
+    esc_code: Option<Code>,
+    data: impl IntoIterator<Item = Token<'a>>,
+) -> std::io::Result<Vec<u8>> {
+    // Same bytes as `encode`, for callers needing the whole stream : the codes are packed in one go
+    let mut writer = BitWriter::default();
+    for token in data {
+        let code = token_code(lookup_table, esc_code, &token)?;
+        writer.write(code.value, code.len);
+    }
+    writer.write(eoc_code.value, eoc_code.len);
+    Ok(writer.finish())
+}
+
+pub fn token_code(
+    lookup_table: &LookupTable,
+    esc_code: Option<Code>,
+    token: &Token,
+) -> std::io::Result<Code> {
+    // Code of the token, or the escape code followed by the byte when the lookup table has none
+    match (token.code(lookup_table), token, esc_code) {
+        (Some(code), _, _) => Ok(code),
+        (None, &Token::Byte(b), Some(esc_code)) => Ok(esc_code.escaped(b)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid character encountered in the input data",
+        )),
+    }
+}
+
+pub fn pack_codes(
+    codes: impl Iterator<Item = std::io::Result<Code>>,
+) -> impl Iterator<Item = std::io::Result<u8>> {
+    // Writes the codes one after the other, the last byte being padded with zeros.
+    // The codes are packed by batches, whose bytes are then yielded one by one.
+    let mut codes = codes.fuse();
+    let mut writer = Some(BitWriter::default());
+    let mut bytes = Vec::new().into_iter();
+    let mut error = None;
+    iter::from_fn(move || loop {
+        if let Some(b) = bytes.next() {
+            return Some(Ok(b));
+        }
+        if let Some(e) = error.take() {
+            return Some(Err(e));
+        }
+        let batch = writer.as_mut()?;
+        let mut n_codes = 0;
+        while n_codes < PACKING_BATCH {
+            match codes.next() {
+                Some(Ok(code)) => batch.write(code.value, code.len),
+                Some(Err(e)) => {
+                    error = Some(e);
+                    break;
                 }
-                Ok(buff
-                    .into_iter()
-                    .enumerate()
-                    .fold(0, |acc_res, (i, curr)| acc_res | (curr << (7 - i))))
+                None => break,
             }
-            Err(e) => Err(e),
-        })
+            n_codes += 1;
+        }
+        bytes = match (n_codes, &error) {
+            (PACKING_BATCH, _) | (_, Some(_)) => batch.take_bytes(),
+            _ => writer.take().map(BitWriter::finish).unwrap_or_default(),
+        }
+        .into_iter();
+    })
 }
 
-pub fn decode(
-    decoding_tree: DecodingTree,
-    eoc_symbol: &'static Symbol,
-    data: impl IntoIterator<Item = u8>,
+pub fn symbol_bytes(
+    symbols: impl Iterator<Item = std::io::Result<Symbol>>,
 ) -> impl Iterator<Item = std::io::Result<u8>> {
-    // Reads an encoded iterator of 0_u8 and 1_u8, binary search for the corresponding utf_8 value and return a stream of those utf_8 values.
-    DecoderUnfold::new(data.into_iter(), decoding_tree, eoc_symbol).map(|x| match x {
-        Ok(symb) => Ok(symb.value),
-        Err(e) => Err(e),
+    // Decoded bytes of the symbols
+    symbols.flat_map(|x| match x {
+        Ok(Symbol {
+            value: Cow::Borrowed(value),
+            ..
+        }) => Either::Left(value.iter().map(|&b| Ok(b))),
+        Ok(Symbol {
+            value: Cow::Owned(value),
+            ..
+        }) => Either::Right(Either::Left(value.into_iter().map(Ok))),
+        Err(e) => Either::Right(Either::Right(iter::once(Err(e)))),
     })
 }
+
+pub fn symbol_bytes_into(
+    symbols: impl Iterator<Item = std::io::Result<Symbol>>,
+    out: &mut Vec<u8>,
+) -> std::io::Result<()> {
+    // Same as `symbol_bytes`, the decoded bytes are appended to the buffer up to the first error
+    for symbol in symbols {
+        out.extend_from_slice(&symbol?.value);
+    }
+    Ok(())
+}
+
+pub const MAX_CODE_LENGTH: u8 = 15;
+
+pub fn huffman_code_lengths(frequencies: &[u64]) -> Vec<u8> {
+    // Computes optimal prefix code lengths (0 for absent symbols) limited to MAX_CODE_LENGTH bits.
+    // When the tree gets too deep, the frequencies are flattened and the tree is rebuilt.
+    let mut frequencies = frequencies.to_vec();
+    loop {
+        let lengths = unbounded_code_lengths(&frequencies);
+        if lengths.iter().all(|&l| l <= MAX_CODE_LENGTH) {
+            return lengths;
+        }
+        frequencies
+            .iter_mut()
+            .filter(|f| **f > 0)
+            .for_each(|f| *f = f.div_ceil(2));
+    }
+}
+
+fn unbounded_code_lengths(frequencies: &[u64]) -> Vec<u8> {
+    let mut lengths = vec![0_u8; frequencies.len()];
+    let used = (0..frequencies.len())
+        .filter(|&i| frequencies[i] > 0)
+        .collect::<Vec<usize>>();
+    if used.len() == 1 {
+        lengths[used[0]] = 1;
+        return lengths;
+    }
+    // Each node of the heap holds the symbols of its subtree, merging two nodes
+    // pushes all of their symbols one level deeper.
+    let mut heap = used
+        .into_iter()
+        .map(|i| Reverse((frequencies[i], vec![i])))
+        .collect::<BinaryHeap<_>>();
+    while heap.len() > 1 {
+        let Reverse((f1, mut s1)) = heap.pop().unwrap();
+        let Reverse((f2, s2)) = heap.pop().unwrap();
+        s1.extend(s2);
+        s1.iter().for_each(|&i| lengths[i] += 1);
+        heap.push(Reverse((f1 + f2, s1)));
+    }
+    lengths
+}
+
+pub fn canonical_codes(lengths: &[u8]) -> Vec<Code> {
+    // Assigns canonical Huffman codes : symbols sorted by (length, index) get consecutive codes.
+    let mut order = (0..lengths.len())
+        .filter(|&i| lengths[i] > 0)
+        .collect::<Vec<usize>>();
+    order.sort_by_key(|&i| (lengths[i], i));
+    let mut codes = vec![Code::default(); lengths.len()];
+    let mut code = 0_u32;
+    let mut prev_len = 0_u8;
+    for i in order {
+        code <<= lengths[i] - prev_len;
+        prev_len = lengths[i];
+        codes[i] = Code {
+            value: code,
+            len: lengths[i],
+        };
+        code += 1;
+    }
+    codes
+}
+
+pub fn is_valid_prefix_code(lengths: &[u8]) -> bool {
+    // Kraft inequality, a canonical code can only be built from such lengths
+    lengths.iter().all(|&l| l <= MAX_CODE_LENGTH)
+        && lengths
+            .iter()
+            .filter(|&&l| l > 0)
+            .map(|&l| 1_u32 << (MAX_CODE_LENGTH - l))
+            .sum::<u32>()
+            <= 1 << MAX_CODE_LENGTH
+}
+
+pub fn pack_code_lengths(lengths: &[u8]) -> Vec<u8> {
+    // Number of lengths followed by the lengths, packed two per byte
+    let mut out = vec![lengths.len() as u8];
+    out.extend(
+        lengths
+            .chunks(2)
+            .map(|l| l[0] << 4 | l.get(1).unwrap_or(&0)),
+    );
+    out
+}
+
+pub fn unpack_code_lengths(data: &mut impl Iterator<Item = u8>) -> Option<Vec<u8>> {
+    let n_lengths = usize::from(data.next()?);
+    let packed = data.take(n_lengths.div_ceil(2)).collect::<Vec<u8>>();
+    if packed.len() != n_lengths.div_ceil(2) {
+        return None;
+    }
+    Some(
+        packed
+            .iter()
+            .flat_map(|b| [b >> 4, b & 0x0F])
+            .take(n_lengths)
+            .collect(),
+    )
+}
@@ -0,0 +1,375 @@
+use std::{
+    cell::{Cell, RefCell},
+    io, iter,
+    rc::Rc,
+    thread,
+};
+
+use crate::fmt::checksum::{
+    crc32, read_checksum, verify_checksum, ChecksumVerifier, Crc32, CHECKSUM_LEN,
+};
+use crate::fmt::index::{IndexEntry, RowIndex, RowIndexer};
+use crate::utils::iterators::Either;
+
+// Blocked body layout : a sequence of (payload length as u32 LE | payload) records ended by a zero length,
+// followed by the row index footer when requested.
+// With checksums, every payload is followed by its CRC-32 (u32 LE) and the end marker by the CRC-32 of the csv.
+// Every payload is a complete, self-contained encoded stream so blocks can be decoded independently.
+pub const BLOCK_END: [u8; 4] = [0; 4];
+
+pub struct BlockSplitter<I: Iterator<Item = u8>> {
+    iter: I,
+    block_size: usize,
+}
+
+impl<I: Iterator<Item = u8>> BlockSplitter<I> {
+    pub fn new(iter: I, block_size: usize) -> Self {
+        BlockSplitter {
+            iter,
+            block_size: block_size.max(1),
+        }
+    }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for BlockSplitter<I> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Blocks are at least block_size bytes long and always end on a newline, except the last one.
+        let mut block = Vec::with_capacity(self.block_size);
+        for b in self.iter.by_ref() {
+            block.push(b);
+            if b == b'\n' && block.len() >= self.block_size {
+                break;
+            }
+        }
+        (!block.is_empty()).then_some(block)
+    }
+}
+
+pub fn map_batch<T: Sync, R: Send>(
+    batch: &[T],
+    f: &(impl Fn(&T) -> io::Result<R> + Sync),
+) -> Vec<io::Result<R>> {
+    // Runs f over the items of the batch, one scoped thread per item
+    if batch.len() < 2 {
+        return batch.iter().map(f).collect();
+    }
+    thread::scope(|s| {
+        batch
+            .iter()
+            .map(|x| s.spawn(move || f(x)))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|h| h.join().expect("Block worker thread panicked"))
+            .collect()
+    })
+}
+
+fn par_map_ordered<T: Sync, R: Send>(
+    items: impl Iterator<Item = T>,
+    jobs: usize,
+    f: impl Fn(&T) -> io::Result<R> + Sync,
+) -> impl Iterator<Item = io::Result<R>> {
+    // Pulls `jobs` items at a time and keeps the input order in the output
+    let mut items = items.peekable();
+    iter::from_fn(move || {
+        items.peek()?;
+        let batch = items.by_ref().take(jobs.max(1)).collect::<Vec<T>>();
+        Some(map_batch(&batch, &f))
+    })
+    .flatten()
+}
+
+pub fn flatten_bytes(
+    blocks: impl Iterator<Item = io::Result<Vec<u8>>>,
+) -> impl Iterator<Item = io::Result<u8>> {
+    blocks.flat_map(|block| match block {
+        Ok(bytes) => Either::Left(bytes.into_iter().map(Ok)),
+        Err(e) => Either::Right(iter::once(Err(e))),
+    })
+}
+
+pub struct BlockWriter<I: Iterator<Item = io::Result<(Vec<u8>, RowIndexer)>>> {
+    // Frames the encoded blocks and keeps track of their offsets to build the row index
+    blocks: I,
+    offset: u64,
+    rows: u64,
+    index: Option<RowIndex>,
+    // Checksum of the raw csv, updated while the blocks are split
+    checksum: Option<Rc<RefCell<Crc32>>>,
+    finished: bool,
+}
+
+impl<I: Iterator<Item = io::Result<(Vec<u8>, RowIndexer)>>> BlockWriter<I> {
+    pub fn new(
+        blocks: I,
+        start_offset: u64,
+        index_interval: Option<u64>,
+        checksum: Option<Rc<RefCell<Crc32>>>,
+    ) -> Self {
+        BlockWriter {
+            blocks,
+            offset: start_offset,
+            rows: 0,
+            index: index_interval.map(|interval| RowIndex {
+                interval,
+                entries: vec![],
+                rows: 0,
+                footer_offset: 0,
+            }),
+            checksum,
+            finished: false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = io::Result<(Vec<u8>, RowIndexer)>>> Iterator for BlockWriter<I> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        match self.blocks.next() {
+            Some(Ok((payload, indexer))) => {
+                let len = match u32::try_from(payload.len()) {
+                    Ok(len) => len,
+                    Err(_) => {
+                        self.finished = true;
+                        return Some(Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "Encoded block is too large",
+                        )));
+                    }
+                };
+                if let Some(index) = self.index.as_mut() {
+                    index
+                        .entries
+                        .extend(indexer.offsets.iter().map(|&(row, bit)| IndexEntry {
+                            row: self.rows + row,
+                            block_offset: self.offset,
+                            bit_offset: bit,
+                        }));
+                }
+                self.rows += indexer.rows();
+                let checksum = self.checksum.is_some().then(|| crc32(&payload));
+                let mut record = len.to_le_bytes().to_vec();
+                record.extend(payload);
+                record.extend(checksum.into_iter().flat_map(u32::to_le_bytes));
+                self.offset += record.len() as u64;
+                Some(Ok(record))
+            }
+            Some(Err(e)) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+            None => {
+                self.finished = true;
+                let mut out = BLOCK_END.to_vec();
+                if let Some(checksum) = self.checksum.take() {
+                    out.extend(checksum.borrow().value().to_le_bytes());
+                }
+                if let Some(mut index) = self.index.take() {
+                    index.rows = self.rows;
+                    index.footer_offset = self.offset + out.len() as u64;
+                    out.extend(index.to_bytes());
+                }
+                Some(Ok(out))
+            }
+        }
+    }
+}
+
+pub fn encode_blocks(
+    data: impl IntoIterator<Item = u8>,
+    block_size: usize,
+    jobs: usize,
+    start_offset: u64,
+    index_interval: Option<u64>,
+    checksums: bool,
+    encode_block: impl Fn(&[u8]) -> io::Result<(Vec<u8>, RowIndexer)> + Sync,
+) -> impl Iterator<Item = io::Result<Vec<u8>>> {
+    // Framed blocks, followed by the end marker and the footer as the last record
+    let checksum = checksums.then(|| Rc::new(RefCell::new(Crc32::new())));
+    let counter = checksum.clone();
+    BlockWriter::new(
+        par_map_ordered(
+            BlockSplitter::new(data.into_iter(), block_size).inspect(move |block| {
+                if let Some(crc) = &counter {
+                    crc.borrow_mut().update(block);
+                }
+            }),
+            jobs,
+            move |block: &Vec<u8>| encode_block(block),
+        ),
+        start_offset,
+        index_interval,
+        checksum,
+    )
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BlockCursor {
+    index: usize,
+    // Byte offset of the next block in the input, used to locate corrupted blocks
+    offset: u64,
+    checksums: bool,
+}
+
+impl BlockCursor {
+    pub fn new(start_offset: u64, checksums: bool) -> Self {
+        BlockCursor {
+            index: 0,
+            offset: start_offset,
+            checksums,
+        }
+    }
+
+    pub fn read_block(
+        &mut self,
+        data: &mut impl Iterator<Item = u8>,
+    ) -> io::Result<Option<Vec<u8>>> {
+        // Payload of the next block, once its checksum is verified, or none at the end marker
+        let truncated = |index: usize| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Truncated bncsv block {}", index),
+            )
+        };
+        let len_bytes = data.take(4).collect::<Vec<u8>>();
+        let len =
+            u32::from_le_bytes(len_bytes.try_into().map_err(|_| truncated(self.index))?) as usize;
+        if len == 0 {
+            return Ok(None);
+        }
+        let payload = data.take(len).collect::<Vec<u8>>();
+        if payload.len() != len {
+            return Err(truncated(self.index));
+        }
+        if self.checksums {
+            let expected = read_checksum(data).map_err(|_| truncated(self.index))?;
+            if crc32(&payload) != expected {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Corrupted bncsv block starting at byte {} : checksum mismatch",
+                        self.offset
+                    ),
+                ));
+            }
+        }
+        self.index += 1;
+        self.offset += (4 + len + if self.checksums { CHECKSUM_LEN } else { 0 }) as u64;
+        Ok(Some(payload))
+    }
+}
+
+pub struct BlockReader<I: Iterator<Item = u8>> {
+    iter: I,
+    cursor: BlockCursor,
+    finished: bool,
+}
+
+impl<I: Iterator<Item = u8>> BlockReader<I> {
+    pub fn new(iter: I, start_offset: u64, checksums: bool) -> Self {
+        BlockReader {
+            iter,
+            cursor: BlockCursor::new(start_offset, checksums),
+            finished: false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = u8>> Iterator for BlockReader<I> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let block = self.cursor.read_block(&mut self.iter);
+        self.finished = !matches!(block, Ok(Some(_)));
+        block.transpose()
+    }
+}
+
+fn decoded_blocks(
+    data: impl Iterator<Item = u8>,
+    jobs: usize,
+    start_offset: u64,
+    checksums: bool,
+    decode_block: impl Fn(&[u8]) -> io::Result<Vec<u8>> + Sync,
+) -> impl Iterator<Item = io::Result<Vec<u8>>> {
+    // Decoded payloads in the order of the blocks, read up to the end marker
+    par_map_ordered(
+        BlockReader::new(data, start_offset, checksums),
+        jobs,
+        move |block: &io::Result<Vec<u8>>| match block {
+            Ok(payload) => decode_block(payload),
+            Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+        },
+    )
+}
+
+pub fn decode_blocks(
+    data: impl IntoIterator<Item = u8>,
+    jobs: usize,
+    start_offset: u64,
+    checksums: bool,
+    decode_block: impl Fn(&[u8]) -> io::Result<Vec<u8>> + Sync,
+) -> impl Iterator<Item = io::Result<u8>> {
+    // The csv checksum follows the end marker, it is read from the same input once all the blocks are decoded
+    let data = Rc::new(RefCell::new(data.into_iter()));
+    let body_len = Rc::new(Cell::new(0_u64));
+    let (reader, counter) = (data.clone(), body_len.clone());
+    let decoded = flatten_bytes(decoded_blocks(
+        iter::from_fn(move || {
+            let b = reader.borrow_mut().next()?;
+            counter.set(counter.get() + 1);
+            Some(b)
+        }),
+        jobs,
+        start_offset,
+        checksums,
+        decode_block,
+    ));
+    if checksums {
+        Either::Left(ChecksumVerifier::new(decoded, move |actual| {
+            let expected = read_checksum(&mut *data.borrow_mut())?;
+            verify_checksum(
+                actual,
+                expected,
+                start_offset..start_offset + body_len.get(),
+            )
+        }))
+    } else {
+        Either::Right(decoded)
+    }
+}
+
+pub fn decode_blocks_into(
+    data: &mut impl Iterator<Item = u8>,
+    jobs: usize,
+    start_offset: u64,
+    checksums: bool,
+    decode_block: impl Fn(&[u8]) -> io::Result<Vec<u8>> + Sync,
+    out: &mut Vec<u8>,
+) -> io::Result<()> {
+    // Same as `decode_blocks`, the decoded blocks are appended to the buffer
+    let start = out.len();
+    let mut body_len = 0_u64;
+    let body = data.by_ref().inspect(|_| body_len += 1);
+    for block in decoded_blocks(body, jobs, start_offset, checksums, decode_block) {
+        out.extend(block?);
+    }
+    if checksums {
+        let expected = read_checksum(data)?;
+        verify_checksum(
+            crc32(&out[start..]),
+            expected,
+            start_offset..start_offset + body_len,
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use std::{io, ops::Range};
+
+// CRC-32 (IEEE 802.3, reflected polynomial 0xEDB88320), the same as zlib and gzip.
+// Blocks carry the checksum of their encoded payload, and the whole stream carries the
+// checksum of the original csv bytes right after its body.
+pub const CHECKSUM_LEN: usize = 4;
+
+const fn build_crc32_tables() -> [[u32; 256]; 8] {
+    // Slicing-by-8 tables : the first one is the usual byte table, table k gives the
+    // checksum of a byte followed by k zero bytes, so that 8 bytes are folded at once.
+    let mut tables = [[0_u32; 256]; 8];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        tables[0][i] = crc;
+        i += 1;
+    }
+    let mut k = 1;
+    while k < 8 {
+        let mut i = 0;
+        while i < 256 {
+            let prev = tables[k - 1][i];
+            tables[k][i] = tables[0][(prev & 0xff) as usize] ^ (prev >> 8);
+            i += 1;
+        }
+        k += 1;
+    }
+    tables
+}
+static CRC32_TABLES: [[u32; 256]; 8] = build_crc32_tables();
+
+#[derive(Copy, Clone, Debug)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32 { state: u32::MAX }
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32::default()
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        self.state = CRC32_TABLES[0][usize::from((self.state as u8) ^ byte)] ^ (self.state >> 8);
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in chunks.by_ref() {
+            let word = u64::from_le_bytes(chunk.try_into().unwrap()) ^ u64::from(self.state);
+            self.state = (0..8).fold(0, |state, i| {
+                state ^ CRC32_TABLES[7 - i][(word >> (8 * i) & 0xff) as usize]
+            });
+        }
+        chunks.remainder().iter().for_each(|&b| self.push(b));
+    }
+
+    pub fn value(&self) -> u32 {
+        !self.state
+    }
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.value()
+}
+
+pub fn read_checksum(data: &mut impl Iterator<Item = u8>) -> io::Result<u32> {
+    let bytes = data.take(CHECKSUM_LEN).collect::<Vec<u8>>();
+    bytes
+        .try_into()
+        .map(u32::from_le_bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated bncsv checksum"))
+}
+
+pub fn verify_checksum(actual: u32, expected: u32, body: Range<u64>) -> io::Result<()> {
+    // Compares the checksum of the decoded csv with the one stored after the body, located by its byte range
+    if actual == expected {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "Corrupted bncsv body between bytes {} and {} : checksum of the decoded csv is {:#010x}, expected {:#010x}",
+            body.start, body.end, actual, expected
+        ),
+    ))
+}
+
+pub struct ChecksumVerifier<I, F>
+where
+    I: Iterator<Item = io::Result<u8>>,
+    F: FnOnce(u32) -> io::Result<()>,
+{
+    // Passes the checksum of the decoded bytes to `verify` once the input is exhausted
+    iter: I,
+    crc: Crc32,
+    verify: Option<F>,
+}
+
+impl<I, F> ChecksumVerifier<I, F>
+where
+    I: Iterator<Item = io::Result<u8>>,
+    F: FnOnce(u32) -> io::Result<()>,
+{
+    pub fn new(iter: I, verify: F) -> Self {
+        ChecksumVerifier {
+            iter,
+            crc: Crc32::new(),
+            verify: Some(verify),
+        }
+    }
+}
+
+impl<I, F> Iterator for ChecksumVerifier<I, F>
+where
+    I: Iterator<Item = io::Result<u8>>,
+    F: FnOnce(u32) -> io::Result<()>,
+{
+    type Item = io::Result<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.iter.next() {
+            Some(Ok(b)) => {
+                self.crc.push(b);
+                Some(Ok(b))
+            }
+            Some(Err(e)) => {
+                // The stream is already reported as invalid, the checksum is not checked
+                self.verify = None;
+                Some(Err(e))
+            }
+            None => (self.verify.take()?)(self.crc.value()).err().map(Err),
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::io;
+
+// Columnar layout, used when the header has FLAG_COLUMNAR : the values of each column are grouped
+// in their own stream, encoded on its own, so that similar values follow each other. A columnar body is :
+// n_runs (u32 LE) | n_runs * (fields (u32 LE), rows (u64 LE))
+// | n_columns (u32 LE) | n_columns * (encoded length (u32 LE) | encoded column)
+// The runs give the number of fields of consecutive rows. Each column holds its values ended by `,`.
+// Rows are split on `\n` and fields on `,`, a trailing newline giving an empty last row, so that
+// any input is rebuilt byte for byte.
+pub type RowShapes = Vec<(u32, u64)>;
+
+// Columns given an encoding of their values, see `fmt::delta` and `fmt::xor`
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub enum ColumnSelection {
+    #[default]
+    None,
+    // Columns estimated to encode shorter this way
+    Auto,
+    // Indexes of the columns, starting at 0
+    Columns(Vec<usize>),
+}
+
+fn invalid_columnar(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid columnar body : {}", msg),
+    )
+}
+
+pub fn transpose(data: &[u8]) -> (RowShapes, Vec<Vec<u8>>) {
+    let rows = data
+        .split(|&b| b == b'\n')
+        .map(|row| row.split(|&b| b == b',').collect::<Vec<&[u8]>>())
+        .collect::<Vec<Vec<&[u8]>>>();
+    let mut shapes: RowShapes = Vec::new();
+    rows.iter().for_each(|row| match shapes.last_mut() {
+        Some((fields, count)) if *fields as usize == row.len() => *count += 1,
+        _ => shapes.push((row.len() as u32, 1)),
+    });
+    let n_columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let mut columns = vec![Vec::new(); n_columns];
+    rows.iter().for_each(|row| {
+        row.iter()
+            .zip(columns.iter_mut())
+            .for_each(|(value, column)| {
+                column.extend_from_slice(value);
+                column.push(b',');
+            })
+    });
+    (shapes, columns)
+}
+
+pub fn untranspose(shapes: &RowShapes, columns: &[Vec<u8>]) -> io::Result<Vec<u8>> {
+    // The runs come from the file, they are checked against the values before any row is rebuilt
+    let n_values = shapes.iter().try_fold(0_u64, |n, &(fields, count)| {
+        n.checked_add(u64::from(fields).checked_mul(count)?)
+    });
+    let n_terminators = columns
+        .iter()
+        .map(|column| column.iter().filter(|&&b| b == b',').count() as u64)
+        .sum::<u64>();
+    if n_values != Some(n_terminators) {
+        return Err(invalid_columnar("number of values does not match the rows"));
+    }
+    let mut values = columns
+        .iter()
+        .map(|column| {
+            column
+                .strip_suffix(b",")
+                .map(|column| column.split(|&b| b == b','))
+                .ok_or_else(|| invalid_columnar("unterminated column"))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut out = Vec::with_capacity(columns.iter().map(|c| c.len()).sum());
+    for (i, &(fields, count)) in shapes.iter().enumerate() {
+        if fields as usize > values.len() {
+            return Err(invalid_columnar("row with more fields than columns"));
+        }
+        for row in 0..count {
+            if i > 0 || row > 0 {
+                out.push(b'\n');
+            }
+            for (k, column) in values.iter_mut().take(fields as usize).enumerate() {
+                if k > 0 {
+                    out.push(b',');
+                }
+                out.extend_from_slice(
+                    column
+                        .next()
+                        .ok_or_else(|| invalid_columnar("missing values"))?,
+                );
+            }
+        }
+    }
+    if values.iter_mut().any(|column| column.next().is_some()) {
+        return Err(invalid_columnar("unused values"));
+    }
+    Ok(out)
+}
+
+pub fn column_values(column: &[u8]) -> impl Iterator<Item = &[u8]> {
+    // Values of a column, each one being ended by `,`
+    column
+        .strip_suffix(b",")
+        .into_iter()
+        .flat_map(|column| column.split(|&b| b == b','))
+}
+
+// Number of values and data of a value section
+pub type ValueSection<'a> = (u64, &'a [u8]);
+
+pub fn value_section(n_values: u64, data: &[u8]) -> Vec<u8> {
+    // Section preceding the encoded text of the columns storing some of their values in binary :
+    // number of values (u64 LE) | length in bytes (u32 LE) | data
+    let mut section = n_values.to_le_bytes().to_vec();
+    section.extend((data.len() as u32).to_le_bytes());
+    section.extend(data);
+    section
+}
+
+pub fn split_value_section(encoded: &[u8]) -> io::Result<(ValueSection<'_>, &[u8])> {
+    // Section, along with the encoded text column following it
+    let (n_values, rest) = encoded
+        .split_first_chunk::<8>()
+        .ok_or_else(|| invalid_columnar("truncated value section"))?;
+    let (len, rest) = rest
+        .split_first_chunk::<4>()
+        .ok_or_else(|| invalid_columnar("truncated value section"))?;
+    let len = u32::from_le_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err(invalid_columnar("truncated value section"));
+    }
+    let (data, rest) = rest.split_at(len);
+    Ok(((u64::from_le_bytes(*n_values), data), rest))
+}
+
+pub fn count_rows(shapes: &RowShapes) -> u64 {
+    // Number of newlines of the body
+    shapes
+        .iter()
+        .map(|&(_, count)| count)
+        .sum::<u64>()
+        .saturating_sub(1)
+}
+
+pub fn write_columnar(shapes: &RowShapes, encoded_columns: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = (shapes.len() as u32).to_le_bytes().to_vec();
+    shapes.iter().for_each(|&(fields, count)| {
+        out.extend(fields.to_le_bytes());
+        out.extend(count.to_le_bytes());
+    });
+    out.extend((encoded_columns.len() as u32).to_le_bytes());
+    encoded_columns.iter().for_each(|column| {
+        out.extend((column.len() as u32).to_le_bytes());
+        out.extend(column);
+    });
+    out
+}
+
+fn read_bytes<const N: usize>(data: &mut impl Iterator<Item = u8>) -> io::Result<[u8; N]> {
+    data.take(N)
+        .collect::<Vec<u8>>()
+        .try_into()
+        .map_err(|_| invalid_columnar("truncated body"))
+}
+
+pub fn read_columnar(data: &mut impl Iterator<Item = u8>) -> io::Result<(RowShapes, Vec<Vec<u8>>)> {
+    // Consumes exactly the columnar body
+    let n_runs = u32::from_le_bytes(read_bytes(data)?);
+    let shapes = (0..n_runs)
+        .map(|_| {
+            let fields = u32::from_le_bytes(read_bytes(data)?);
+            if fields == 0 {
+                // Every row has at least one field, even an empty row
+                return Err(invalid_columnar("run of rows without fields"));
+            }
+            Ok((fields, u64::from_le_bytes(read_bytes(data)?)))
+        })
+        .collect::<io::Result<RowShapes>>()?;
+    let n_columns = u32::from_le_bytes(read_bytes(data)?);
+    let columns = (0..n_columns)
+        .map(|_| {
+            let len = u32::from_le_bytes(read_bytes(data)?) as usize;
+            let column = data.take(len).collect::<Vec<u8>>();
+            if column.len() != len {
+                return Err(invalid_columnar("truncated column"));
+            }
+            Ok(column)
+        })
+        .collect::<io::Result<Vec<Vec<u8>>>>()?;
+    Ok((shapes, columns))
+}
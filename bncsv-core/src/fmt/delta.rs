@@ -0,0 +1,55 @@
+use std::io;
+
+use crate::fmt::columnar::column_values;
+use crate::fmt::scaled::{format_scaled, parse_scaled, MAX_SCALE};
+
+// Delta encoding of the columns of columnar streams : the values of a column holding integers, or decimals
+// with a fixed number of fractional digits, are replaced by their difference with the previous value,
+// written as an integer number of units of the last digit. Empty values are kept empty and skipped.
+// Only values written back exactly from their number are accepted (no `+`, leading zeros or `-0`),
+// so that decoding rebuilds the same text.
+pub fn column_scale(column: &[u8]) -> Option<u8> {
+    // Number of fractional digits of a column that can be delta encoded
+    let scale = column_values(column)
+        .find(|value| !value.is_empty())
+        .map_or(0, |value| {
+            value
+                .iter()
+                .position(|&b| b == b'.')
+                .map_or(0, |dot| value.len() - dot - 1)
+        });
+    let scale = u8::try_from(scale).ok().filter(|&s| s <= MAX_SCALE)?;
+    delta_encode(column, scale).map(|_| scale)
+}
+
+pub fn delta_encode(column: &[u8], scale: u8) -> Option<Vec<u8>> {
+    let mut previous = 0_i64;
+    let mut out = Vec::with_capacity(column.len());
+    for value in column_values(column) {
+        if !value.is_empty() {
+            let value = parse_scaled(value, scale)?;
+            out.extend(value.checked_sub(previous)?.to_string().as_bytes());
+            previous = value;
+        }
+        out.push(b',');
+    }
+    Some(out)
+}
+
+pub fn delta_decode(column: &[u8], scale: u8) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid delta encoded column");
+    let mut previous = 0_i64;
+    let mut out = Vec::with_capacity(column.len());
+    for delta in column_values(column) {
+        if !delta.is_empty() {
+            let delta = std::str::from_utf8(delta)
+                .ok()
+                .and_then(|delta| delta.parse::<i64>().ok())
+                .ok_or_else(invalid)?;
+            previous = previous.checked_add(delta).ok_or_else(invalid)?;
+            out.extend(format_scaled(previous, scale));
+        }
+        out.push(b',');
+    }
+    Ok(out)
+}
@@ -0,0 +1,139 @@
+use std::io;
+
+pub const MAGIC: [u8; 4] = *b"BNCS";
+pub const FORMAT_VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 7;
+
+// The body is split in independently decodable blocks, see `fmt::block`
+pub const FLAG_BLOCKS: u8 = 0b0000_0001;
+// A row index footer follows the body, see `fmt::index`
+pub const FLAG_INDEX: u8 = 0b0000_0010;
+// Blocks and the whole decoded csv are checked against CRC-32 checksums, see `fmt::checksum`
+pub const FLAG_CHECKSUM: u8 = 0b0000_0100;
+// Newlines decode as CRLF, see `fmt::line_endings`
+pub const FLAG_CRLF: u8 = 0b0000_1000;
+// Line endings were converted to LF when encoding
+pub const FLAG_NORMALIZED_EOL: u8 = 0b0001_0000;
+// A metadata section follows the header, see `fmt::metadata`
+pub const FLAG_METADATA: u8 = 0b0010_0000;
+// The values of each column are encoded in their own stream, see `fmt::columnar`
+pub const FLAG_COLUMNAR: u8 = 0b0100_0000;
+const KNOWN_FLAGS: u8 = FLAG_BLOCKS
+    | FLAG_INDEX
+    | FLAG_CHECKSUM
+    | FLAG_CRLF
+    | FLAG_NORMALIZED_EOL
+    | FLAG_METADATA
+    | FLAG_COLUMNAR;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[repr(u8)]
+pub enum Codebook {
+    // First hard-coded Huffman codes of `fmt::utf8`, digits and separators only
+    Utf8Numeric = 1,
+    // Canonical Huffman codes built from the input symbol frequencies, their lengths follow the header
+    Adaptive = 2,
+    // Hard-coded Huffman codes of `fmt::utf8`, with extension symbols (exponents, special values, CR)
+    Utf8Static = 3,
+    // Columnar streams only : each column gets its own canonical Huffman codes or the hard-coded ones,
+    // their code lengths are stored in the metadata section
+    PerColumn = 4,
+    // Symbols range coded with their quantized frequencies in the input, stored after the header, see `fmt::range`
+    Range = 5,
+    // Canonical Huffman codes for each previous symbol, their lengths follow the header
+    Context = 6,
+}
+
+impl TryFrom<u8> for Codebook {
+    type Error = io::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Codebook::Utf8Numeric),
+            2 => Ok(Codebook::Adaptive),
+            3 => Ok(Codebook::Utf8Static),
+            4 => Ok(Codebook::PerColumn),
+            5 => Ok(Codebook::Range),
+            6 => Ok(Codebook::Context),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown codebook identifier {}", value),
+            )),
+        }
+    }
+}
+
+// Fixed-size header starting every bncsv stream :
+// magic (4 bytes) | format version (1 byte) | codebook id (1 byte) | flags (1 byte)
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Header {
+    pub version: u8,
+    pub codebook: Codebook,
+    pub flags: u8,
+}
+
+impl Header {
+    pub fn new(codebook: Codebook) -> Self {
+        Header {
+            version: FORMAT_VERSION,
+            codebook,
+            flags: 0,
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; HEADER_LEN] {
+        [
+            MAGIC[0],
+            MAGIC[1],
+            MAGIC[2],
+            MAGIC[3],
+            self.version,
+            self.codebook as u8,
+            self.flags,
+        ]
+    }
+
+    pub fn read(data: &mut impl Iterator<Item = u8>) -> io::Result<Header> {
+        // Consumes exactly HEADER_LEN bytes of the iterator and validates them.
+        let bytes = data.take(HEADER_LEN).collect::<Vec<u8>>();
+        if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a bncsv stream : missing magic bytes",
+            ));
+        }
+        if bytes.len() != HEADER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Truncated bncsv header",
+            ));
+        }
+        if bytes[4] == 0 || bytes[4] > FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Unsupported bncsv format version {} (this build reads up to {})",
+                    bytes[4], FORMAT_VERSION
+                ),
+            ));
+        }
+        if bytes[6] & !KNOWN_FLAGS != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported bncsv header flags {:#010b}", bytes[6]),
+            ));
+        }
+        let codebook = Codebook::try_from(bytes[5])?;
+        if codebook == Codebook::PerColumn && bytes[6] & FLAG_COLUMNAR == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The per-column codebook needs a columnar body",
+            ));
+        }
+        Ok(Header {
+            version: bytes[4],
+            codebook,
+            flags: bytes[6],
+        })
+    }
+}
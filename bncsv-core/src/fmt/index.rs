@@ -0,0 +1,186 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+// Row index footer, written after the body when the header has FLAG_INDEX :
+// n_entries (u64 LE) | n_entries * (row, block_offset, bit_offset) (3 x u64 LE) | interval (u64 LE)
+// | rows (u64 LE) | footer offset (u64 LE) | footer length (u32 LE) | magic
+// The trailing length and magic allow to find the footer by seeking from the end of the stream,
+// and the offset of the footer from the start of its stream to find the start of that stream.
+// Along with the number of rows of each stream, this gives the rows of concatenated streams.
+pub const INDEX_MAGIC: [u8; 4] = *b"BNIX";
+// interval, rows, footer offset, footer length and magic
+const TRAILER_LEN: usize = 32;
+const ENTRY_LEN: usize = 24;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct IndexEntry {
+    // Number of the first row decoded from this entry
+    pub row: u64,
+    // Byte offset, from the start of the stream, of the block holding the row.
+    // For streams without blocks, this is the start of the body.
+    pub block_offset: u64,
+    // Bit offset of the row inside the encoded symbols of the block
+    pub bit_offset: u64,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct RowIndex {
+    pub interval: u64,
+    pub entries: Vec<IndexEntry>,
+    // Number of newlines of the csv of the stream, header row excluded
+    pub rows: u64,
+    // Byte offset of the footer from the start of the stream
+    pub footer_offset: u64,
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+fn invalid_index(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid row index : {}", msg),
+    )
+}
+
+fn entries_len(n_entries: u64) -> io::Result<usize> {
+    // Length of the entries of the footer, the count comes from the file
+    usize::try_from(n_entries)
+        .ok()
+        .and_then(|n| n.checked_mul(ENTRY_LEN))
+        .filter(|len| len.checked_add(8 + TRAILER_LEN).is_some())
+        .ok_or_else(|| invalid_index("too many entries"))
+}
+
+impl RowIndex {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = (self.entries.len() as u64).to_le_bytes().to_vec();
+        self.entries.iter().for_each(|e| {
+            out.extend(e.row.to_le_bytes());
+            out.extend(e.block_offset.to_le_bytes());
+            out.extend(e.bit_offset.to_le_bytes());
+        });
+        out.extend(self.interval.to_le_bytes());
+        out.extend(self.rows.to_le_bytes());
+        out.extend(self.footer_offset.to_le_bytes());
+        out.extend(((out.len() + 8) as u32).to_le_bytes());
+        out.extend(INDEX_MAGIC);
+        out
+    }
+
+    fn from_bytes(bytes: &[u8]) -> io::Result<RowIndex> {
+        // bytes holds the whole footer, trailer included
+        if bytes.len() < 8 + TRAILER_LEN {
+            return Err(invalid_index("truncated footer"));
+        }
+        let entries_len = entries_len(read_u64(bytes))?;
+        if bytes.len().checked_sub(8 + TRAILER_LEN) != Some(entries_len) {
+            return Err(invalid_index("footer length does not match its entries"));
+        }
+        if bytes[bytes.len() - 4..] != INDEX_MAGIC {
+            return Err(invalid_index("missing magic bytes"));
+        }
+        let entries = bytes[8..8 + entries_len]
+            .chunks(ENTRY_LEN)
+            .map(|e| IndexEntry {
+                row: read_u64(e),
+                block_offset: read_u64(&e[8..]),
+                bit_offset: read_u64(&e[16..]),
+            })
+            .collect::<Vec<IndexEntry>>();
+        if entries.windows(2).any(|w| w[0].row > w[1].row) {
+            return Err(invalid_index("entries are not sorted by row"));
+        }
+        let trailer = &bytes[bytes.len() - TRAILER_LEN..];
+        Ok(RowIndex {
+            interval: read_u64(trailer),
+            entries,
+            rows: read_u64(&trailer[8..]),
+            footer_offset: read_u64(&trailer[16..]),
+        })
+    }
+
+    pub fn read(data: &mut impl Iterator<Item = u8>, footer_offset: u64) -> io::Result<RowIndex> {
+        // Reads the footer from its first byte, when the stream is read forward up to the given offset
+        let mut footer = data.take(8).collect::<Vec<u8>>();
+        if footer.len() != 8 {
+            return Err(invalid_index("truncated footer"));
+        }
+        let entries_len = entries_len(read_u64(&footer))?;
+        footer.extend(data.take(entries_len + TRAILER_LEN));
+        let index = RowIndex::from_bytes(&footer)?;
+        if index.footer_offset != footer_offset {
+            return Err(invalid_index("footer offset does not match its stream"));
+        }
+        Ok(index)
+    }
+
+    pub fn read_before<R: Read + Seek>(reader: &mut R, end: u64) -> io::Result<(RowIndex, u64)> {
+        // Footer ending at the given position of the input, along with the position of the start of its stream
+        let mut trailer = [0_u8; 8];
+        let trailer_start = end
+            .checked_sub(8)
+            .ok_or_else(|| invalid_index("truncated footer"))?;
+        reader.seek(SeekFrom::Start(trailer_start))?;
+        reader.read_exact(&mut trailer)?;
+        if trailer[4..] != INDEX_MAGIC {
+            return Err(invalid_index("missing magic bytes"));
+        }
+        let footer_len = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+        let footer_start = end
+            .checked_sub(u64::from(footer_len))
+            .ok_or_else(|| invalid_index("truncated footer"))?;
+        let mut footer = vec![0_u8; footer_len as usize];
+        reader.seek(SeekFrom::Start(footer_start))?;
+        reader.read_exact(&mut footer)?;
+        let index = RowIndex::from_bytes(&footer)?;
+        let stream_start = footer_start
+            .checked_sub(index.footer_offset)
+            .ok_or_else(|| invalid_index("footer offset past the start of the input"))?;
+        Ok((index, stream_start))
+    }
+
+    pub fn lookup(&self, row: u64) -> Option<&IndexEntry> {
+        // Last entry starting at or before the given row
+        self.entries.iter().rev().find(|e| e.row <= row)
+    }
+}
+
+pub struct RowIndexer {
+    // Collects the (row, bit offset) of the first row and of every interval-th row of encoded data
+    interval: u64,
+    rows: u64,
+    bits: u64,
+    pub offsets: Vec<(u64, u64)>,
+}
+
+impl RowIndexer {
+    pub fn new(interval: u64) -> Self {
+        RowIndexer {
+            interval: interval.max(1),
+            rows: 0,
+            bits: 0,
+            offsets: vec![(0, 0)],
+        }
+    }
+
+    pub fn push(&mut self, value: &[u8], bit_len: u64) {
+        // Rows start after the symbols ending with a newline
+        self.bits += bit_len;
+        if value.last() == Some(&b'\n') {
+            self.rows += 1;
+            if self.rows.is_multiple_of(self.interval) {
+                self.offsets.push((self.rows, self.bits));
+            }
+        }
+    }
+
+    pub fn skip_rows(&mut self, rows: u64) {
+        // Rows that can't be indexed, such as the rows of a columnar body
+        self.rows += rows;
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+}
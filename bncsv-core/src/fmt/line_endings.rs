@@ -0,0 +1,38 @@
+use std::iter;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum LineEndings {
+    // Line endings are reproduced exactly, streams whose first line ends with CRLF are flagged as CRLF streams
+    #[default]
+    Preserve,
+    // CRLF and lone CR line endings are converted to LF, the header records that it was done
+    Normalize,
+}
+
+pub fn read_first_line(data: &mut impl Iterator<Item = u8>) -> Vec<u8> {
+    // Consumes the input up to the first newline included
+    let mut line = Vec::new();
+    for b in data.by_ref() {
+        line.push(b);
+        if b == b'\n' {
+            break;
+        }
+    }
+    line
+}
+
+pub fn normalize_line_endings(
+    data: impl Iterator<Item = u8>,
+    mut on_normalized: impl FnMut(),
+) -> impl Iterator<Item = u8> {
+    let mut data = data.peekable();
+    iter::from_fn(move || {
+        let b = data.next()?;
+        if b != b'\r' {
+            return Some(b);
+        }
+        data.next_if_eq(&b'\n');
+        on_normalized();
+        Some(b'\n')
+    })
+}
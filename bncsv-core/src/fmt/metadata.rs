@@ -0,0 +1,334 @@
+use std::io;
+
+use crate::compr::{pack_code_lengths, unpack_code_lengths, BYTE_VALUES};
+use crate::fmt::checksum::crc32;
+use crate::fmt::scaled::MAX_SCALE;
+
+// Metadata section following the header when `FLAG_METADATA` is set :
+// section length (u32 LE) | entries, each one being tag (u8) | value length (u32 LE) | value
+// Only the values differing from the defaults are written, unknown tags are skipped when reading.
+pub const TAG_DELIMITER: u8 = 1;
+pub const TAG_DECIMAL_MARK: u8 = 2;
+pub const TAG_HEADER_ROW: u8 = 3;
+// Number of columns (u32 LE) followed by the packed code lengths of each column
+pub const TAG_COLUMN_TABLES: u8 = 4;
+// Number of columns (u32 LE) followed by, for each column, whether it is delta encoded (u8) and its scale (u8)
+pub const TAG_DELTA_COLUMNS: u8 = 5;
+// Number of columns (u32 LE) followed by, for each column, whether its floats are XOR encoded (u8)
+pub const TAG_XOR_COLUMNS: u8 = 6;
+// Same layout as the delta encoded columns, for the columns stored as decimal-scaled integers
+pub const TAG_SCALED_COLUMNS: u8 = 7;
+// Number of tokens (u8) followed by, for each token, its length (u8) and its bytes
+pub const TAG_TOKENS: u8 = 8;
+// Minimum number of csv bytes of the blocks (u64 LE)
+pub const TAG_BLOCK_SIZE: u8 = 9;
+// CRC-32 (u32 LE) of the entries before it, written last when the stream has checksums
+pub const TAG_CHECKSUM: u8 = 10;
+
+// Field delimiters accepted when encoding
+pub const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+pub const DECIMAL_MARKS: [u8; 2] = [b'.', b','];
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum HeaderRow {
+    // The first line is a header row when the codebook can't encode it
+    #[default]
+    Detect,
+    Present,
+    Absent,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Metadata {
+    pub delimiter: u8,
+    pub decimal_mark: u8,
+    // First line of the csv with its line ending, re-emitted before the decoded rows as is
+    pub header_row: Option<Vec<u8>>,
+    // Code lengths of each column with the per-column codebook, empty for the hard-coded codebook
+    pub column_tables: Vec<Vec<u8>>,
+    // Number of fractional digits of each delta encoded column, see `fmt::delta`
+    pub delta_columns: Vec<Option<u8>>,
+    // Columns whose floats are XOR encoded, see `fmt::xor`
+    pub xor_columns: Vec<bool>,
+    // Number of fractional digits of each column stored as decimal-scaled integers, see `fmt::scaled`
+    pub scaled_columns: Vec<Option<u8>>,
+    // Multi-byte tokens learned from the input, appended to the alphabet of the fitted codebooks
+    pub tokens: Vec<Vec<u8>>,
+    // Block size the stream was encoded with, kept so that appended rows get blocks of the same size
+    pub block_size: Option<usize>,
+}
+
+impl Default for Metadata {
+    fn default() -> Self {
+        Metadata {
+            delimiter: b',',
+            decimal_mark: b'.',
+            header_row: None,
+            column_tables: Vec::new(),
+            delta_columns: Vec::new(),
+            xor_columns: Vec::new(),
+            scaled_columns: Vec::new(),
+            tokens: Vec::new(),
+            block_size: None,
+        }
+    }
+}
+
+impl Metadata {
+    pub fn is_default(&self) -> bool {
+        *self == Metadata::default()
+    }
+
+    pub fn validate(&self) -> io::Result<()> {
+        if !DELIMITERS.contains(&self.delimiter) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Unsupported field delimiter {:?}",
+                    char::from(self.delimiter)
+                ),
+            ));
+        }
+        if !DECIMAL_MARKS.contains(&self.decimal_mark) || self.decimal_mark == self.delimiter {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Unsupported decimal mark {:?} with the field delimiter {:?}",
+                    char::from(self.decimal_mark),
+                    char::from(self.delimiter)
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self, checksum: bool) -> Vec<u8> {
+        let defaults = Metadata::default();
+        let mut entries = Vec::new();
+        let mut push_entry = |tag: u8, value: &[u8]| {
+            entries.push(tag);
+            entries.extend((value.len() as u32).to_le_bytes());
+            entries.extend(value);
+        };
+        if self.delimiter != defaults.delimiter {
+            push_entry(TAG_DELIMITER, &[self.delimiter]);
+        }
+        if self.decimal_mark != defaults.decimal_mark {
+            push_entry(TAG_DECIMAL_MARK, &[self.decimal_mark]);
+        }
+        if let Some(header_row) = &self.header_row {
+            push_entry(TAG_HEADER_ROW, header_row);
+        }
+        if !self.column_tables.is_empty() {
+            let mut tables = (self.column_tables.len() as u32).to_le_bytes().to_vec();
+            self.column_tables
+                .iter()
+                .for_each(|lengths| tables.extend(pack_code_lengths(lengths)));
+            push_entry(TAG_COLUMN_TABLES, &tables);
+        }
+        for (tag, scales) in [
+            (TAG_DELTA_COLUMNS, &self.delta_columns),
+            (TAG_SCALED_COLUMNS, &self.scaled_columns),
+        ] {
+            if !scales.is_empty() {
+                let mut columns = (scales.len() as u32).to_le_bytes().to_vec();
+                scales.iter().for_each(|scale| match scale {
+                    Some(scale) => columns.extend([1, *scale]),
+                    None => columns.extend([0, 0]),
+                });
+                push_entry(tag, &columns);
+            }
+        }
+        if !self.tokens.is_empty() {
+            let mut tokens = vec![self.tokens.len() as u8];
+            self.tokens.iter().for_each(|token| {
+                tokens.push(token.len() as u8);
+                tokens.extend(token);
+            });
+            push_entry(TAG_TOKENS, &tokens);
+        }
+        if !self.xor_columns.is_empty() {
+            let mut columns = (self.xor_columns.len() as u32).to_le_bytes().to_vec();
+            columns.extend(self.xor_columns.iter().map(|&xor| u8::from(xor)));
+            push_entry(TAG_XOR_COLUMNS, &columns);
+        }
+        if let Some(block_size) = self.block_size {
+            push_entry(TAG_BLOCK_SIZE, &(block_size as u64).to_le_bytes());
+        }
+        if checksum {
+            let crc = crc32(&entries);
+            entries.push(TAG_CHECKSUM);
+            entries.extend(4_u32.to_le_bytes());
+            entries.extend(crc.to_le_bytes());
+        }
+        let mut out = (entries.len() as u32).to_le_bytes().to_vec();
+        out.extend(entries);
+        out
+    }
+
+    pub fn read(data: &mut impl Iterator<Item = u8>) -> io::Result<(Metadata, usize)> {
+        // Consumes the whole section and returns its length in bytes
+        let truncated = || io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated bncsv metadata");
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let read_u32 = |data: &mut dyn Iterator<Item = u8>| {
+            let bytes = data.take(4).collect::<Vec<u8>>();
+            bytes
+                .try_into()
+                .map(|b| u32::from_le_bytes(b) as usize)
+                .map_err(|_| truncated())
+        };
+        let section_len = read_u32(data)?;
+        let section = data.take(section_len).collect::<Vec<u8>>();
+        if section.len() != section_len {
+            return Err(truncated());
+        }
+        let mut metadata = Metadata::default();
+        let mut entries = section.iter().copied();
+        loop {
+            let entry_start = section.len() - entries.len();
+            let Some(tag) = entries.next() else {
+                break;
+            };
+            let len = read_u32(&mut entries)?;
+            let value = entries.by_ref().take(len).collect::<Vec<u8>>();
+            if value.len() != len {
+                return Err(truncated());
+            }
+            match (tag, value.as_slice()) {
+                (TAG_DELIMITER, &[b]) => metadata.delimiter = b,
+                (TAG_DECIMAL_MARK, &[b]) => metadata.decimal_mark = b,
+                (TAG_HEADER_ROW, _) => metadata.header_row = Some(value),
+                (TAG_COLUMN_TABLES, _) => {
+                    metadata.column_tables = read_column_tables(&value)
+                        .ok_or_else(|| invalid(format!("Invalid bncsv metadata entry {}", tag)))?
+                }
+                (TAG_DELTA_COLUMNS, _) => {
+                    metadata.delta_columns = read_column_scales(&value)
+                        .ok_or_else(|| invalid(format!("Invalid bncsv metadata entry {}", tag)))?
+                }
+                (TAG_SCALED_COLUMNS, _) => {
+                    metadata.scaled_columns = read_column_scales(&value)
+                        .ok_or_else(|| invalid(format!("Invalid bncsv metadata entry {}", tag)))?
+                }
+                (TAG_XOR_COLUMNS, _) => {
+                    metadata.xor_columns = read_xor_columns(&value)
+                        .ok_or_else(|| invalid(format!("Invalid bncsv metadata entry {}", tag)))?
+                }
+                (TAG_TOKENS, _) => {
+                    metadata.tokens = read_tokens(&value)
+                        .ok_or_else(|| invalid(format!("Invalid bncsv metadata entry {}", tag)))?
+                }
+                (TAG_BLOCK_SIZE, _) => {
+                    metadata.block_size = Some(
+                        value
+                            .try_into()
+                            .ok()
+                            .and_then(|b| usize::try_from(u64::from_le_bytes(b)).ok())
+                            .ok_or_else(|| invalid(format!("Invalid bncsv metadata entry {}", tag)))?,
+                    )
+                }
+                (TAG_CHECKSUM, &[a, b, c, d]) => {
+                    let actual = crc32(&section[..entry_start]);
+                    let expected = u32::from_le_bytes([a, b, c, d]);
+                    if actual != expected {
+                        return Err(invalid(format!(
+                            "Corrupted bncsv metadata : checksum of the section is {:#010x}, expected {:#010x}",
+                            actual, expected
+                        )));
+                    }
+                }
+                (TAG_DELIMITER | TAG_DECIMAL_MARK | TAG_CHECKSUM, _) => {
+                    return Err(invalid(format!("Invalid bncsv metadata entry {}", tag)))
+                }
+                _ => {}
+            }
+        }
+        metadata
+            .validate()
+            .map_err(|e| invalid(format!("Invalid bncsv metadata : {}", e)))?;
+        Ok((metadata, 4 + section_len))
+    }
+
+    pub fn column_names(&self) -> Option<Vec<String>> {
+        // Fields of the header row, split on the delimiter
+        let header_row = String::from_utf8_lossy(self.header_row.as_ref()?);
+        let line = header_row.trim_end_matches(['\r', '\n']);
+        Some(
+            line.split(char::from(self.delimiter))
+                .map(|name| name.to_string())
+                .collect(),
+        )
+    }
+
+    pub fn encoding_map(&self) -> [u8; 256] {
+        // Permutation of the bytes sending the delimiter and the decimal mark to the `,` and `.`
+        // of the codebooks, the decoded bytes go through the inverse permutation.
+        let mut map = BYTE_VALUES;
+        map.swap(usize::from(self.delimiter), usize::from(b','));
+        let dot = map.iter().position(|&b| b == b'.').unwrap();
+        map.swap(usize::from(self.decimal_mark), dot);
+        map
+    }
+
+    pub fn decoding_map(&self) -> [u8; 256] {
+        let mut map = BYTE_VALUES;
+        self.encoding_map()
+            .iter()
+            .enumerate()
+            .for_each(|(b, &encoded)| map[usize::from(encoded)] = b as u8);
+        map
+    }
+}
+
+fn read_column_tables(value: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut data = value.iter().copied();
+    let n_columns = u32::from_le_bytes(data.by_ref().take(4).collect::<Vec<u8>>().try_into().ok()?);
+    let tables = (0..n_columns)
+        .map(|_| unpack_code_lengths(&mut data))
+        .collect::<Option<Vec<Vec<u8>>>>()?;
+    data.next().is_none().then_some(tables)
+}
+
+fn read_column_scales(value: &[u8]) -> Option<Vec<Option<u8>>> {
+    let (n_columns, columns) = value.split_first_chunk::<4>()?;
+    if columns.len() != 2 * u32::from_le_bytes(*n_columns) as usize {
+        return None;
+    }
+    columns
+        .chunks(2)
+        .map(|column| match *column {
+            [0, 0] => Some(None),
+            [1, scale] if scale <= MAX_SCALE => Some(Some(scale)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn read_tokens(value: &[u8]) -> Option<Vec<Vec<u8>>> {
+    // Tokens hold at least two bytes, shorter ones are already symbols of the alphabet
+    let mut data = value.iter().copied();
+    let n_tokens = data.next()?;
+    let tokens = (0..n_tokens)
+        .map(|_| {
+            let len = usize::from(data.next()?);
+            let token = data.by_ref().take(len).collect::<Vec<u8>>();
+            (len >= 2 && token.len() == len).then_some(token)
+        })
+        .collect::<Option<Vec<Vec<u8>>>>()?;
+    data.next().is_none().then_some(tokens)
+}
+
+fn read_xor_columns(value: &[u8]) -> Option<Vec<bool>> {
+    let (n_columns, columns) = value.split_first_chunk::<4>()?;
+    if columns.len() != u32::from_le_bytes(*n_columns) as usize {
+        return None;
+    }
+    columns
+        .iter()
+        .map(|&xor| match xor {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        })
+        .collect()
+}
@@ -1 +1,13 @@
-pub mod utf8;
\ No newline at end of file
+pub mod block;
+pub mod checksum;
+pub mod columnar;
+pub mod delta;
+pub mod header;
+pub mod index;
+pub mod line_endings;
+pub mod metadata;
+pub mod range;
+pub mod scaled;
+pub mod stream;
+pub mod utf8;
+pub mod xor;
@@ -0,0 +1,186 @@
+use std::io;
+
+// Range coder used by the range codebook, an alternative to the Huffman codes that spends fractional
+// bits per symbol. Each symbol narrows a 32 bits range proportionally to its frequency, the frequencies
+// summing to 2^FREQ_BITS. The carries are propagated as in LZMA's range coder : the encoder output
+// starts with a zero byte and ends with 4 bytes flushing the low end of the range, so that the decoder
+// reads exactly the bytes written by the encoder.
+pub const FREQ_BITS: u32 = 15;
+const TOP: u32 = 1 << 24;
+
+fn invalid_range(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid range coded data : {}", msg),
+    )
+}
+
+pub fn quantize_frequencies(counts: &[u64]) -> Vec<u16> {
+    // Frequencies summing to 2^FREQ_BITS, the symbols that occur keep a non-zero frequency.
+    // The rounding error goes to the most frequent symbol.
+    let total = counts.iter().sum::<u64>().max(1);
+    let mut freqs = counts
+        .iter()
+        .map(|&c| match c {
+            0 => 0,
+            c => ((u128::from(c) << FREQ_BITS) / u128::from(total)).max(1) as i64,
+        })
+        .collect::<Vec<i64>>();
+    let (max_index, _) = counts
+        .iter()
+        .enumerate()
+        .max_by_key(|&(i, &c)| (c, std::cmp::Reverse(i)))
+        .unwrap_or((0, &0));
+    let others = freqs.iter().sum::<i64>() - freqs.get(max_index).copied().unwrap_or(0);
+    if let Some(max) = freqs.get_mut(max_index) {
+        *max = (1_i64 << FREQ_BITS) - others;
+    }
+    freqs.into_iter().map(|f| f as u16).collect()
+}
+
+pub fn pack_frequencies(freqs: &[u16]) -> Vec<u8> {
+    // Number of frequencies followed by the frequencies (u16 LE), the trailing zeros are left out
+    let freqs = &freqs[..freqs.iter().rposition(|&f| f > 0).map_or(0, |i| i + 1)];
+    let mut out = vec![freqs.len() as u8];
+    freqs.iter().for_each(|f| out.extend(f.to_le_bytes()));
+    out
+}
+
+pub fn unpack_frequencies(data: &mut impl Iterator<Item = u8>) -> Option<Vec<u16>> {
+    let n_freqs = usize::from(data.next()?);
+    let bytes = data.take(2 * n_freqs).collect::<Vec<u8>>();
+    if bytes.len() != 2 * n_freqs {
+        return None;
+    }
+    Some(
+        bytes
+            .chunks(2)
+            .map(|f| u16::from_le_bytes([f[0], f[1]]))
+            .collect(),
+    )
+}
+
+pub fn cumulative_frequencies(freqs: &[u16]) -> Vec<u32> {
+    // Start of the interval of each symbol, followed by the total
+    let mut cumulative = vec![0_u32];
+    freqs.iter().fold(0_u32, |acc, &f| {
+        cumulative.push(acc + u32::from(f));
+        acc + u32::from(f)
+    });
+    cumulative
+}
+
+pub struct RangeEncoder {
+    low: u64,
+    range: u32,
+    // Last byte not written yet, it may still get a carry, followed by cache_size - 1 bytes of 0xFF
+    cache: u8,
+    cache_size: u64,
+    out: Vec<u8>,
+}
+
+impl Default for RangeEncoder {
+    fn default() -> Self {
+        RangeEncoder {
+            low: 0,
+            range: u32::MAX,
+            cache: 0,
+            cache_size: 1,
+            out: Vec::new(),
+        }
+    }
+}
+
+impl RangeEncoder {
+    pub fn encode(&mut self, start: u32, freq: u32, total_bits: u32) {
+        // Narrows the range to the interval [start, start + freq) out of 2^total_bits
+        let r = self.range >> total_bits;
+        self.low += u64::from(r) * u64::from(start);
+        self.range = r * freq;
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low();
+        }
+    }
+
+    fn shift_low(&mut self) {
+        if (self.low as u32) < 0xFF00_0000 || self.low >> 32 != 0 {
+            let carry = (self.low >> 32) as u8;
+            let mut byte = self.cache;
+            while self.cache_size > 0 {
+                self.out.push(byte.wrapping_add(carry));
+                byte = 0xFF;
+                self.cache_size -= 1;
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.cache_size += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        (0..5).for_each(|_| self.shift_low());
+        self.out
+    }
+}
+
+pub struct RangeDecoder<I: Iterator<Item = u8>> {
+    iter: I,
+    code: u32,
+    range: u32,
+}
+
+impl<I: Iterator<Item = u8>> RangeDecoder<I> {
+    pub fn new(mut iter: I) -> io::Result<Self> {
+        let bytes = iter.by_ref().take(5).collect::<Vec<u8>>();
+        if bytes.len() != 5 {
+            return Err(invalid_range("truncated data"));
+        }
+        if bytes[0] != 0 {
+            return Err(invalid_range("wrong first byte"));
+        }
+        Ok(RangeDecoder {
+            iter,
+            code: bytes[1..].iter().fold(0, |acc, &b| acc << 8 | u32::from(b)),
+            range: u32::MAX,
+        })
+    }
+
+    fn narrow(&mut self, r: u32, start: u32, freq: u32) -> io::Result<()> {
+        self.code -= r * start;
+        self.range = r * freq;
+        while self.range < TOP {
+            let b = self
+                .iter
+                .next()
+                .ok_or_else(|| invalid_range("truncated data"))?;
+            self.range <<= 8;
+            self.code = self.code << 8 | u32::from(b);
+        }
+        Ok(())
+    }
+
+    pub fn decode(&mut self, cumulative: &[u32]) -> io::Result<usize> {
+        // Index of the symbol whose interval holds the code, the cumulative frequencies summing to 2^FREQ_BITS
+        let r = self.range >> FREQ_BITS;
+        let value = self.code / r;
+        if value >= 1 << FREQ_BITS {
+            return Err(invalid_range("code out of the range"));
+        }
+        let symbol = cumulative.partition_point(|&c| c <= value) - 1;
+        let (start, end) = (cumulative[symbol], cumulative[symbol + 1]);
+        self.narrow(r, start, end - start)?;
+        Ok(symbol)
+    }
+
+    pub fn decode_bits(&mut self, total_bits: u32) -> io::Result<u32> {
+        // Value encoded with the same frequency for each of the 2^total_bits values
+        let r = self.range >> total_bits;
+        let value = self.code / r;
+        if value >= 1 << total_bits {
+            return Err(invalid_range("code out of the range"));
+        }
+        self.narrow(r, value, 1)?;
+        Ok(value)
+    }
+}
@@ -0,0 +1,153 @@
+use std::io;
+
+use crate::fmt::columnar::{column_values, value_section, ValueSection};
+
+// Decimal-scaled encoding of the columns of columnar streams : numbers with a fixed number of fractional
+// digits are stored as their integer number of units of the last digit (the mantissa), the scale of the
+// column being stored in the metadata section. Only numbers written back exactly from their mantissa are
+// stored this way (no `+`, leading zeros, missing or extra fractional digits or `-0`), the other fields
+// stay in the text column encoded with the codebook. The value section holds a LEB128 varint per field :
+// 0 for the next value of the text column, else the zigzag encoded mantissa plus 1.
+
+// Digits of a mantissa fitting in an i64
+pub const MAX_SCALE: u8 = 18;
+
+pub fn parse_scaled(value: &[u8], scale: u8) -> Option<i64> {
+    // Value in units of its last digit, when writing it back gives the same text
+    let scale = usize::from(scale);
+    let (negative, digits) = match value.strip_prefix(b"-") {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+    let (int, frac) = if scale == 0 {
+        (digits, &b""[..])
+    } else {
+        let (int, frac) = digits.split_at(digits.len().checked_sub(scale + 1)?);
+        (int, frac.strip_prefix(b".")?)
+    };
+    if int.is_empty() || (int[0] == b'0' && int.len() > 1) {
+        return None;
+    }
+    let magnitude = int.iter().chain(frac).try_fold(0_i64, |acc, &d| {
+        if !d.is_ascii_digit() {
+            return None;
+        }
+        acc.checked_mul(10)?.checked_add(i64::from(d - b'0'))
+    })?;
+    match (negative, magnitude) {
+        (true, 0) => None,
+        (true, magnitude) => Some(-magnitude),
+        (false, magnitude) => Some(magnitude),
+    }
+}
+
+pub fn format_scaled(value: i64, scale: u8) -> Vec<u8> {
+    let scale = usize::from(scale);
+    let digits = format!("{:0>width$}", value.unsigned_abs(), width = scale + 1);
+    let (int, frac) = digits.split_at(digits.len() - scale);
+    let mut out = if value < 0 { b"-".to_vec() } else { Vec::new() };
+    out.extend(int.as_bytes());
+    if scale > 0 {
+        out.push(b'.');
+        out.extend(frac.as_bytes());
+    }
+    out
+}
+
+pub fn detect_scale(column: &[u8]) -> u8 {
+    // Most common number of fractional digits among the numbers of the column
+    let mut counts = [0_u64; MAX_SCALE as usize + 1];
+    column_values(column)
+        .filter(|value| {
+            !value.is_empty()
+                && value
+                    .iter()
+                    .all(|&b| b.is_ascii_digit() || b == b'.' || b == b'-')
+        })
+        .map(|value| {
+            value
+                .iter()
+                .position(|&b| b == b'.')
+                .map_or(0, |dot| value.len() - dot - 1)
+        })
+        .filter(|&scale| scale <= usize::from(MAX_SCALE))
+        .for_each(|scale| counts[scale] += 1);
+    (0..=MAX_SCALE)
+        .max_by_key(|&scale| (counts[usize::from(scale)], std::cmp::Reverse(scale)))
+        .unwrap()
+}
+
+fn push_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut impl Iterator<Item = u8>) -> io::Result<u64> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid scaled column");
+    let mut value = 0_u64;
+    for shift in (0..64).step_by(7) {
+        let b = data.next().ok_or_else(invalid)?;
+        value |= u64::from(b & 0x7F)
+            .checked_shl(shift)
+            .filter(|v| v >> shift == u64::from(b & 0x7F))
+            .ok_or_else(invalid)?;
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid())
+}
+
+pub fn scaled_encode(column: &[u8], scale: u8) -> (Vec<u8>, Vec<u8>) {
+    // Value section and text column of the fields that aren't stored as mantissas
+    let mut varints = Vec::new();
+    let mut text = Vec::new();
+    let mut n_values = 0_u64;
+    for value in column_values(column) {
+        n_values += 1;
+        match parse_scaled(value, scale) {
+            Some(mantissa) => push_varint(
+                &mut varints,
+                ((mantissa << 1) ^ (mantissa >> 63)) as u64 + 1,
+            ),
+            None => {
+                varints.push(0);
+                text.extend(value);
+                text.push(b',');
+            }
+        }
+    }
+    (value_section(n_values, &varints), text)
+}
+
+pub fn scaled_decode(section: ValueSection, text: &[u8], scale: u8) -> io::Result<Vec<u8>> {
+    // Column rebuilt from its value section and its decoded text column
+    let invalid = |msg: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid scaled column : {}", msg),
+        )
+    };
+    let (n_values, varints) = section;
+    let mut varints = varints.iter().copied();
+    let mut text = column_values(text);
+    let mut out = Vec::new();
+    for _ in 0..n_values {
+        match read_varint(&mut varints)? {
+            0 => out.extend(text.next().ok_or_else(|| invalid("missing text value"))?),
+            zigzag => {
+                let zigzag = zigzag - 1;
+                let mantissa = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+                out.extend(format_scaled(mantissa, scale));
+            }
+        }
+        out.push(b',');
+    }
+    if text.next().is_some() || varints.next().is_some() {
+        return Err(invalid("unused values"));
+    }
+    Ok(out)
+}
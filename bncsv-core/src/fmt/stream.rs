@@ -0,0 +1,314 @@
+use std::{
+    cell::Cell,
+    io::{self, BufRead, BufReader, Read, Write},
+};
+
+use crate::fmt::block::{map_batch, BlockCursor};
+use crate::fmt::checksum::{read_checksum, verify_checksum, Crc32};
+use crate::fmt::columnar::ColumnSelection;
+use crate::fmt::header::{Codebook, Header, FLAG_BLOCKS, FLAG_CHECKSUM, FLAG_INDEX};
+use crate::fmt::index::RowIndex;
+use crate::fmt::metadata::Metadata;
+use crate::fmt::utf8::{
+    decode_body, decode_frame_body_into, read_bytes, read_stream_start, DecodingOptions,
+    EncodingOptions, RowEncoder, Utf8Converter,
+};
+
+// `std::io` adapters of the conversions : `BncsvEncoder` is a `Write` encoding the csv written to it,
+// `BncsvDecoder` a `Read` decoding the bncsv streams of its reader.
+
+pub struct BncsvEncoder<W: Write> {
+    // Writes the csv written to it as a bncsv stream, completed by `finish` (or when dropped)
+    writer: Option<W>,
+    options: EncodingOptions,
+    // Input not encoded yet, the hard-coded codebook only waits for the end of the current row.
+    // The other codebooks are fitted to the whole input, which is kept until `finish`.
+    pending: Vec<u8>,
+    rows: Option<RowEncoder>,
+}
+
+impl<W: Write> BncsvEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        BncsvEncoder::with_options(&EncodingOptions::default(), writer)
+    }
+
+    pub fn with_options(options: &EncodingOptions, writer: W) -> Self {
+        BncsvEncoder {
+            writer: Some(writer),
+            options: options.clone(),
+            pending: Vec::new(),
+            rows: None,
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        // Encodes the last rows and ends the stream, the writer is given back
+        let mut writer = self.writer.take().ok_or_else(abandoned_stream_error)?;
+        self.end_stream(&mut writer)?;
+        Ok(writer)
+    }
+
+    fn write_rows(&mut self, appended: usize) -> io::Result<Vec<u8>> {
+        // Encoded bytes of the complete rows written so far, when the codebook allows it.
+        // Only the `appended` last bytes can end a row, the ones before them hold no newline.
+        if !streams_rows(&self.options) {
+            return Ok(Vec::new());
+        }
+        let start = self.pending.len() - appended;
+        let Some(end) = self.pending[start..].iter().rposition(|&b| b == b'\n') else {
+            return Ok(Vec::new());
+        };
+        let rows = self.pending.drain(..=start + end).collect::<Vec<u8>>();
+        match self.rows.as_mut() {
+            Some(encoder) => encoder.push(&self.options, &rows),
+            None => {
+                let (encoder, bytes) = RowEncoder::start(&self.options, &rows)?;
+                self.rows = Some(encoder);
+                Ok(bytes)
+            }
+        }
+    }
+
+    fn end_stream(&mut self, writer: &mut W) -> io::Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        let bytes = if streams_rows(&self.options) {
+            let (mut bytes, encoder) = match self.rows.take() {
+                Some(mut encoder) => (encoder.push(&self.options, &pending)?, encoder),
+                None => {
+                    let (encoder, bytes) = RowEncoder::start(&self.options, &pending)?;
+                    (bytes, encoder)
+                }
+            };
+            bytes.extend(encoder.finish(&self.options));
+            bytes
+        } else {
+            let mut encoded = Vec::new();
+            Utf8Converter::encode_into_with_options(&self.options, &pending, &mut encoded)?;
+            encoded
+        };
+        writer.write_all(&bytes)?;
+        writer.flush()
+    }
+}
+
+impl<W: Write> Write for BncsvEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A failed write abandons the stream, nothing else is written
+        let mut writer = self.writer.take().ok_or_else(abandoned_stream_error)?;
+        self.pending.extend_from_slice(buf);
+        writer.write_all(&self.write_rows(buf.len())?)?;
+        self.writer = Some(writer);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // Only the encoded bytes are flushed, the current row and the last bits wait for the next rows
+        match self.writer.as_mut() {
+            Some(writer) => writer.flush(),
+            None => Err(abandoned_stream_error()),
+        }
+    }
+}
+
+impl<W: Write> Drop for BncsvEncoder<W> {
+    fn drop(&mut self) {
+        // Errors can't be reported here, `finish` should be called to get them
+        if let Some(mut writer) = self.writer.take() {
+            let _ = self.end_stream(&mut writer);
+        }
+    }
+}
+
+fn streams_rows(options: &EncodingOptions) -> bool {
+    // Whether the rows can be encoded as they come, without fitting anything to the whole input
+    options.codebook == Codebook::Utf8Static
+        && options.block_size.is_none()
+        && !options.columnar
+        && !options.tokens
+        && options.delta == ColumnSelection::None
+        && options.xor_floats == ColumnSelection::None
+        && options.scaled == ColumnSelection::None
+}
+
+fn abandoned_stream_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "The bncsv stream was abandoned after an error",
+    )
+}
+
+pub struct BncsvDecoder<R: Read> {
+    // Csv decoded from the bncsv stream (or concatenated streams) of the reader.
+    // Streams split in blocks are decoded a batch of blocks at a time, the other ones as a whole.
+    reader: BufReader<R>,
+    options: DecodingOptions,
+    // Offset of the next byte of the input
+    position: u64,
+    // Stream whose blocks are being decoded, none between streams
+    blocks: Option<BlockStream>,
+    // Csv decoded last, given by the reads until all of it is consumed
+    decoded: Vec<u8>,
+    consumed: usize,
+    // Error met after some rows of the stream were decoded, reported once they are read
+    error: Option<io::Error>,
+    // Set at the end of the input, or once an error is reported
+    finished: bool,
+}
+
+struct BlockStream {
+    header: Header,
+    metadata: Metadata,
+    frame_start: u64,
+    body_start: u64,
+    cursor: BlockCursor,
+    // Checksum of the csv of the blocks decoded so far
+    crc: Crc32,
+}
+
+impl<R: Read> BncsvDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        BncsvDecoder::with_options(&DecodingOptions::default(), reader)
+    }
+
+    pub fn with_options(options: &DecodingOptions, reader: R) -> Self {
+        BncsvDecoder {
+            reader: BufReader::new(reader),
+            options: options.clone(),
+            position: 0,
+            blocks: None,
+            decoded: Vec::new(),
+            consumed: 0,
+            error: None,
+            finished: false,
+        }
+    }
+
+    fn decode_stream(&mut self) -> io::Result<()> {
+        // Starts the next stream, the input can only end after the first one. A stream without blocks
+        // is decoded whole, the header row of the other ones is given before their first block.
+        // A failure of the reader is reported instead of the truncated stream it leads to.
+        if self.position > 0 && self.reader.fill_buf()?.is_empty() {
+            self.finished = true;
+            return Ok(());
+        }
+        let (mut read_error, len) = (None, Cell::new(0));
+        let mut bytes =
+            read_bytes(&mut self.reader, &mut read_error).inspect(|_| len.set(len.get() + 1));
+        let data = &mut bytes;
+        let frame_start = self.position;
+        let decoded = read_stream_start(data).and_then(|(header, metadata, body_offset)| {
+            let body_start = frame_start + body_offset;
+            if header.flags & FLAG_BLOCKS == 0 {
+                return decode_frame_body_into(
+                    &self.options,
+                    header,
+                    &metadata,
+                    data,
+                    frame_start,
+                    body_start,
+                    &mut self.decoded,
+                );
+            }
+            self.decoded
+                .extend_from_slice(metadata.header_row.as_deref().unwrap_or_default());
+            self.blocks = Some(BlockStream {
+                header,
+                metadata,
+                frame_start,
+                body_start,
+                cursor: BlockCursor::new(body_start, header.flags & FLAG_CHECKSUM != 0),
+                crc: Crc32::new(),
+            });
+            Ok(())
+        });
+        drop(bytes);
+        self.position += len.get();
+        match read_error {
+            Some(e) => Err(e),
+            None => decoded,
+        }
+    }
+
+    fn decode_blocks(&mut self, mut blocks: BlockStream) -> io::Result<()> {
+        // Decodes as many blocks as there are jobs, each one verified against its checksum first.
+        // The end marker is followed by the checksum of the csv and the row index footer.
+        let (mut read_error, len) = (None, Cell::new(0));
+        let mut bytes =
+            read_bytes(&mut self.reader, &mut read_error).inspect(|_| len.set(len.get() + 1));
+        let data = &mut bytes;
+        let mut payloads = Vec::new();
+        let mut ended = Ok(false);
+        while payloads.len() < self.options.jobs.max(1) {
+            match blocks.cursor.read_block(data) {
+                Ok(Some(payload)) => payloads.push(payload),
+                Ok(None) => {
+                    ended = Ok(true);
+                    break;
+                }
+                Err(e) => {
+                    ended = Err(e);
+                    break;
+                }
+            }
+        }
+        let decoded = map_batch(&payloads, &|payload: &Vec<u8>| {
+            decode_body(blocks.header, &blocks.metadata, payload, 0)
+        })
+        .into_iter()
+        .try_for_each(|block| {
+            let block = block?;
+            blocks.crc.update(&block);
+            self.decoded.extend(block);
+            Ok(())
+        })
+        .and(ended)
+        .and_then(|ended| {
+            if !ended {
+                return Ok(Some(blocks));
+            }
+            let body_end = self.position + len.get();
+            if blocks.header.flags & FLAG_CHECKSUM != 0 {
+                let expected = read_checksum(data)?;
+                verify_checksum(blocks.crc.value(), expected, blocks.body_start..body_end)?;
+            }
+            if blocks.header.flags & FLAG_INDEX != 0 {
+                RowIndex::read(data, self.position + len.get() - blocks.frame_start)?;
+            }
+            Ok(None)
+        });
+        drop(bytes);
+        self.position += len.get();
+        match (read_error, decoded) {
+            (Some(e), _) | (None, Err(e)) => Err(e),
+            (None, Ok(blocks)) => {
+                self.blocks = blocks;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for BncsvDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // The rows kept by a failed stream are read before its error
+        while self.consumed == self.decoded.len() && !self.finished {
+            if let Some(e) = self.error.take() {
+                self.finished = true;
+                return Err(e);
+            }
+            self.decoded.clear();
+            self.consumed = 0;
+            let decoded = match self.blocks.take() {
+                Some(blocks) => self.decode_blocks(blocks),
+                None => self.decode_stream(),
+            };
+            if let Err(e) = decoded {
+                self.error = Some(e);
+            }
+        }
+        let n = buf.len().min(self.decoded.len() - self.consumed);
+        buf[..n].copy_from_slice(&self.decoded[self.consumed..self.consumed + n]);
+        self.consumed += n;
+        Ok(n)
+    }
+}
@@ -1,118 +1,2329 @@
-use std::io::{self};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    cmp::Reverse,
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    iter,
+    ops::Range,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
-use crate::compr::{decode, encode, BnCsvConverter, DecodingTree, Symbol};
+use crate::compr::{
+    canonical_codes, encode, encode_packed, huffman_code_lengths, is_valid_prefix_code,
+    pack_code_lengths, symbol_bytes, symbol_bytes_into, token_code, tokenize, unpack_code_lengths,
+    BnCsvConverter, Code, DecoderUnfold, DecodingTable, LookupTable, Symbol, Token, BYTE_VALUES,
+    ESCAPED_BYTE_BITS,
+};
+use crate::fmt::block::{
+    decode_blocks, decode_blocks_into, encode_blocks, flatten_bytes, BlockReader,
+};
+use crate::fmt::checksum::{
+    crc32, read_checksum, verify_checksum, ChecksumVerifier, Crc32, CHECKSUM_LEN,
+};
+use crate::fmt::columnar::{
+    count_rows, read_columnar, split_value_section, transpose, untranspose, write_columnar,
+    ColumnSelection,
+};
+use crate::fmt::delta::{column_scale, delta_decode, delta_encode};
+use crate::fmt::header::{
+    Codebook, Header, FLAG_BLOCKS, FLAG_CHECKSUM, FLAG_COLUMNAR, FLAG_CRLF, FLAG_INDEX,
+    FLAG_METADATA, FLAG_NORMALIZED_EOL, HEADER_LEN,
+};
+use crate::fmt::index::{IndexEntry, RowIndex, RowIndexer};
+use crate::fmt::line_endings::{normalize_line_endings, read_first_line, LineEndings};
+use crate::fmt::metadata::{HeaderRow, Metadata};
+use crate::fmt::range::{
+    cumulative_frequencies, pack_frequencies, quantize_frequencies, unpack_frequencies,
+    RangeDecoder, RangeEncoder, FREQ_BITS,
+};
+use crate::fmt::scaled::{detect_scale, scaled_decode, scaled_encode};
+use crate::fmt::xor::{xor_decode, xor_encode};
+use crate::utils::bits::{BitReader, BitWriter};
+use crate::utils::iterators::Either;
 pub const SYMB_COMMA: Symbol = Symbol {
-    bits: &[0, 0, 1],
-    value: 44,
+    code: Code::from_bits(&[0, 0, 1]),
+    value: Cow::Borrowed(b","),
 };
 pub const SYMB_DOT: Symbol = Symbol {
-    bits: &[0, 1, 0],
-    value: 46,
+    code: Code::from_bits(&[0, 1, 0]),
+    value: Cow::Borrowed(b"."),
 };
 pub const SYMB_NEWLINE: Symbol = Symbol {
-    bits: &[0, 1, 1, 0, 0, 1],
-    value: 10,
+    code: Code::from_bits(&[0, 1, 1, 0, 0, 1]),
+    value: Cow::Borrowed(b"\n"),
 };
 pub const SYMB_MINUS: Symbol = Symbol {
-    bits: &[0, 1, 1, 0, 1],
-    value: 45,
+    code: Code::from_bits(&[0, 1, 1, 0, 1]),
+    value: Cow::Borrowed(b"-"),
 };
 pub const SYMB_5: Symbol = Symbol {
-    bits: &[0, 0, 0],
-    value: 53,
+    code: Code::from_bits(&[0, 0, 0]),
+    value: Cow::Borrowed(b"5"),
 };
 pub const SYMB_0: Symbol = Symbol {
-    bits: &[0, 1, 1, 1],
-    value: 48,
+    code: Code::from_bits(&[0, 1, 1, 1]),
+    value: Cow::Borrowed(b"0"),
 };
 pub const SYMB_1: Symbol = Symbol {
-    bits: &[1, 0, 1, 0],
-    value: 49,
+    code: Code::from_bits(&[1, 0, 1, 0]),
+    value: Cow::Borrowed(b"1"),
 };
 pub const SYMB_2: Symbol = Symbol {
-    bits: &[1, 1, 1, 0],
-    value: 50,
+    code: Code::from_bits(&[1, 1, 1, 0]),
+    value: Cow::Borrowed(b"2"),
 };
 pub const SYMB_3: Symbol = Symbol {
-    bits: &[1, 1, 0, 1],
-    value: 51,
+    code: Code::from_bits(&[1, 1, 0, 1]),
+    value: Cow::Borrowed(b"3"),
 };
 pub const SYMB_4: Symbol = Symbol {
-    bits: &[1, 0, 0, 1],
-    value: 52,
+    code: Code::from_bits(&[1, 0, 0, 1]),
+    value: Cow::Borrowed(b"4"),
 };
 pub const SYMB_6: Symbol = Symbol {
-    bits: &[1, 1, 0, 0],
-    value: 54,
+    code: Code::from_bits(&[1, 1, 0, 0]),
+    value: Cow::Borrowed(b"6"),
 };
 pub const SYMB_7: Symbol = Symbol {
-    bits: &[1, 1, 1, 1],
-    value: 55,
+    code: Code::from_bits(&[1, 1, 1, 1]),
+    value: Cow::Borrowed(b"7"),
 };
 pub const SYMB_8: Symbol = Symbol {
-    bits: &[1, 0, 1, 1],
-    value: 56,
+    code: Code::from_bits(&[1, 0, 1, 1]),
+    value: Cow::Borrowed(b"8"),
 };
 pub const SYMB_9: Symbol = Symbol {
-    bits: &[1, 0, 0, 0],
-    value: 57,
+    code: Code::from_bits(&[1, 0, 0, 0]),
+    value: Cow::Borrowed(b"9"),
+};
+// End of compression symbol of the `Utf8Numeric` codebook.
+// The `Utf8Static` codebook splits its leaf between a longer EOC and the extension prefix 0110001.
+pub const SYMB_NUMERIC_EOC: Symbol = Symbol {
+    code: Code::from_bits(&[0, 1, 1, 0, 0, 0]),
+    value: Cow::Borrowed(b""),
 };
 pub const SYMB_EOC: Symbol = Symbol {
-    bits: &[0, 1, 1, 0, 0, 0],
-    value: 45,
-};
-
-const INVALID_SYMBOL: Result<&[u8], &str> = Err("Invalid character encountered in the input data");
-
-const fn build_utf8_lookup_table() -> [Result<&'static [u8], &'static str>; 255] {
-    let mut table = [INVALID_SYMBOL; 255];
-    table[10] = Ok(SYMB_NEWLINE.bits);
-    table[13] = Ok(&[]);
-    table[44] = Ok(SYMB_COMMA.bits);
-    table[45] = Ok(SYMB_MINUS.bits);
-    table[46] = Ok(SYMB_DOT.bits);
-    table[48] = Ok(SYMB_0.bits);
-    table[49] = Ok(SYMB_1.bits);
-    table[50] = Ok(SYMB_2.bits);
-    table[51] = Ok(SYMB_3.bits);
-    table[52] = Ok(SYMB_4.bits);
-    table[53] = Ok(SYMB_5.bits);
-    table[54] = Ok(SYMB_6.bits);
-    table[55] = Ok(SYMB_7.bits);
-    table[56] = Ok(SYMB_8.bits);
-    table[57] = Ok(SYMB_9.bits);
+    code: Code::from_bits(&[0, 1, 1, 0, 0, 0, 0]),
+    value: Cow::Borrowed(b""),
+};
+// Extension symbols : the prefix followed by a 4 bits extension code.
+// Unassigned extension codes are invalid when decoding.
+pub const SYMB_LOWER_E: Symbol = Symbol {
+    code: Code::from_bits(&[0, 1, 1, 0, 0, 0, 1, 0, 0, 0, 0]),
+    value: Cow::Borrowed(b"e"),
+};
+pub const SYMB_UPPER_E: Symbol = Symbol {
+    code: Code::from_bits(&[0, 1, 1, 0, 0, 0, 1, 0, 0, 0, 1]),
+    value: Cow::Borrowed(b"E"),
+};
+pub const SYMB_PLUS: Symbol = Symbol {
+    code: Code::from_bits(&[0, 1, 1, 0, 0, 0, 1, 0, 0, 1, 0]),
+    value: Cow::Borrowed(b"+"),
+};
+// Special float values, matched as a whole by the encoder
+pub const SYMB_NAN: Symbol = Symbol {
+    code: Code::from_bits(&[0, 1, 1, 0, 0, 0, 1, 0, 0, 1, 1]),
+    value: Cow::Borrowed(b"NaN"),
+};
+pub const SYMB_LOWER_NAN: Symbol = Symbol {
+    code: Code::from_bits(&[0, 1, 1, 0, 0, 0, 1, 0, 1, 0, 0]),
+    value: Cow::Borrowed(b"nan"),
+};
+pub const SYMB_INF: Symbol = Symbol {
+    code: Code::from_bits(&[0, 1, 1, 0, 0, 0, 1, 0, 1, 0, 1]),
+    value: Cow::Borrowed(b"inf"),
+};
+pub const SYMB_UPPER_INF: Symbol = Symbol {
+    code: Code::from_bits(&[0, 1, 1, 0, 0, 0, 1, 0, 1, 1, 0]),
+    value: Cow::Borrowed(b"Inf"),
+};
+pub const SYMB_INFINITY: Symbol = Symbol {
+    code: Code::from_bits(&[0, 1, 1, 0, 0, 0, 1, 0, 1, 1, 1]),
+    value: Cow::Borrowed(b"Infinity"),
+};
+
+// Line ending symbols. Carriage returns get their own code, and streams written with CRLF line endings
+// decode the newline code as CRLF, a bare line feed then needs its own code.
+pub const SYMB_CR: Symbol = Symbol {
+    code: Code::from_bits(&[0, 1, 1, 0, 0, 0, 1, 1, 0, 0, 0]),
+    value: Cow::Borrowed(b"\r"),
+};
+pub const SYMB_LF: Symbol = Symbol {
+    code: Code::from_bits(&[0, 1, 1, 0, 0, 0, 1, 1, 0, 0, 1]),
+    value: Cow::Borrowed(b"\n"),
+};
+pub const SYMB_CRLF: Symbol = Symbol {
+    code: Code::from_bits(&[0, 1, 1, 0, 0, 1]),
+    value: Cow::Borrowed(b"\r\n"),
+};
+// Followed by the 8 bits of a byte missing from the codebook
+pub const SYMB_ESC: Symbol = Symbol {
+    code: Code::from_bits(&[0, 1, 1, 0, 0, 0, 1, 1, 0, 1, 0]),
+    value: Cow::Borrowed(b""),
+};
+
+const fn build_utf8_lookup_table(crlf: bool) -> LookupTable {
+    let mut table = [None; 256];
+    table[10] = if crlf {
+        Some(SYMB_LF.code)
+    } else {
+        Some(SYMB_NEWLINE.code)
+    };
+    table[13] = Some(SYMB_CR.code);
+    table[43] = Some(SYMB_PLUS.code);
+    table[69] = Some(SYMB_UPPER_E.code);
+    table[101] = Some(SYMB_LOWER_E.code);
+    table[44] = Some(SYMB_COMMA.code);
+    table[45] = Some(SYMB_MINUS.code);
+    table[46] = Some(SYMB_DOT.code);
+    table[48] = Some(SYMB_0.code);
+    table[49] = Some(SYMB_1.code);
+    table[50] = Some(SYMB_2.code);
+    table[51] = Some(SYMB_3.code);
+    table[52] = Some(SYMB_4.code);
+    table[53] = Some(SYMB_5.code);
+    table[54] = Some(SYMB_6.code);
+    table[55] = Some(SYMB_7.code);
+    table[56] = Some(SYMB_8.code);
+    table[57] = Some(SYMB_9.code);
     table
 }
-static UTF_8_LOOKUP_ENCODING_TABLE: [Result<&[u8], &str>; 255] = build_utf8_lookup_table();
+static UTF_8_LOOKUP_ENCODING_TABLE: LookupTable = build_utf8_lookup_table(false);
+static UTF_8_CRLF_LOOKUP_ENCODING_TABLE: LookupTable = build_utf8_lookup_table(true);
+
+// Alphabet of the codebooks, the order gives the symbol index used by adaptive code tables.
+// New symbols are appended after the EOC so that older adaptive tables keep their meaning.
+pub const SYMBOLS: [Symbol; 26] = [
+    SYMB_0,
+    SYMB_1,
+    SYMB_2,
+    SYMB_3,
+    SYMB_4,
+    SYMB_5,
+    SYMB_6,
+    SYMB_7,
+    SYMB_8,
+    SYMB_9,
+    SYMB_COMMA,
+    SYMB_DOT,
+    SYMB_MINUS,
+    SYMB_NEWLINE,
+    SYMB_EOC,
+    SYMB_LOWER_E,
+    SYMB_UPPER_E,
+    SYMB_PLUS,
+    SYMB_NAN,
+    SYMB_LOWER_NAN,
+    SYMB_INF,
+    SYMB_UPPER_INF,
+    SYMB_INFINITY,
+    SYMB_CR,
+    SYMB_LF,
+    SYMB_ESC,
+];
+const NEWLINE_INDEX: usize = 13;
+const EOC_INDEX: usize = 14;
+const ESC_INDEX: usize = 25;
+// Multi-byte symbols of the alphabet, in matching order
+static TOKENS: [Symbol; 5] = [
+    SYMB_INFINITY,
+    SYMB_UPPER_INF,
+    SYMB_INF,
+    SYMB_NAN,
+    SYMB_LOWER_NAN,
+];
+static CRLF_TOKENS: [Symbol; 6] = [
+    SYMB_CRLF,
+    SYMB_INFINITY,
+    SYMB_UPPER_INF,
+    SYMB_INF,
+    SYMB_NAN,
+    SYMB_LOWER_NAN,
+];
+const NUMERIC_SYMBOLS: [Symbol; 15] = [
+    SYMB_0,
+    SYMB_1,
+    SYMB_2,
+    SYMB_3,
+    SYMB_4,
+    SYMB_5,
+    SYMB_6,
+    SYMB_7,
+    SYMB_8,
+    SYMB_9,
+    SYMB_COMMA,
+    SYMB_DOT,
+    SYMB_MINUS,
+    SYMB_NEWLINE,
+    SYMB_NUMERIC_EOC,
+];
+
+#[derive(Clone, Debug)]
+pub struct EncodingOptions {
+    pub codebook: Codebook,
+    // Splits the input in independently encoded blocks of at least this many bytes
+    pub block_size: Option<usize>,
+    // Number of blocks encoded in parallel
+    pub jobs: usize,
+    // Writes a row index footer with an entry every this many rows
+    pub index_interval: Option<u64>,
+    // Writes CRC-32 checksums of the blocks and of the csv, verified when decoding
+    pub checksum: bool,
+    pub line_endings: LineEndings,
+    // Encodes the values of each column in their own stream, the whole input (or block) is read first
+    pub columnar: bool,
+    // Columns whose values are replaced by their differences, implies the columnar layout
+    pub delta: ColumnSelection,
+    // Columns whose floats are XORed with the previous one, implies the columnar layout
+    pub xor_floats: ColumnSelection,
+    // Columns whose numbers are stored as integers scaled by their number of decimals, implies the columnar layout
+    pub scaled: ColumnSelection,
+    // Learns multi-byte tokens such as `0.` from the input and gives them their own codes
+    pub tokens: bool,
+    // Whether the first line holds the column names, stored as is in the metadata section
+    pub header_row: HeaderRow,
+    // Field delimiter and decimal mark of the csv, stored in the metadata section when not `,` and `.`
+    pub delimiter: u8,
+    pub decimal_mark: u8,
+    // Filled while the encoded stream is consumed
+    pub stats: Arc<EncodingStats>,
+}
+
+impl Default for EncodingOptions {
+    fn default() -> Self {
+        EncodingOptions {
+            codebook: Codebook::Utf8Static,
+            block_size: None,
+            jobs: 1,
+            index_interval: None,
+            checksum: true,
+            line_endings: LineEndings::Preserve,
+            columnar: false,
+            delta: ColumnSelection::None,
+            xor_floats: ColumnSelection::None,
+            scaled: ColumnSelection::None,
+            tokens: false,
+            header_row: HeaderRow::Detect,
+            delimiter: b',',
+            decimal_mark: b'.',
+            stats: Arc::default(),
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct EncodingStats {
+    normalized_line_endings: AtomicU64,
+    escaped_bytes: AtomicU64,
+}
+
+impl EncodingStats {
+    pub fn normalized_line_endings(&self) -> u64 {
+        self.normalized_line_endings.load(Ordering::Relaxed)
+    }
+
+    pub fn escaped_bytes(&self) -> u64 {
+        self.escaped_bytes.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DecodingOptions {
+    // Number of blocks decoded in parallel
+    pub jobs: usize,
+}
+
+impl Default for DecodingOptions {
+    fn default() -> Self {
+        DecodingOptions { jobs: 1 }
+    }
+}
+
+// Most tokens learned from an input, and number of candidates tried
+const MAX_TOKENS: usize = 8;
+const MAX_TOKEN_CANDIDATES: usize = 16;
+// Bytes of each text looked at when learning the tokens
+const TOKEN_SAMPLE_LEN: usize = 1 << 20;
+
+struct Alphabet {
+    // Symbols of the fitted codebooks, the learned tokens following the hard-coded symbols
+    symbols: Vec<Symbol>,
+    // Multi-byte symbols given to the tokenizer, in matching order
+    tokens: Vec<Symbol>,
+}
+
+impl Alphabet {
+    fn new(crlf: bool, learned: &[Vec<u8>]) -> Self {
+        // The newline decodes as CRLF in CRLF streams
+        let (_, static_tokens, _) = static_codes(crlf);
+        let mut symbols = SYMBOLS.to_vec();
+        if crlf {
+            symbols[NEWLINE_INDEX] = SYMB_CRLF;
+        }
+        let mut tokens = learned
+            .iter()
+            .map(|token| Symbol {
+                code: Code::default(),
+                value: Cow::Owned(token.clone()),
+            })
+            .collect::<Vec<Symbol>>();
+        symbols.extend(tokens.iter().cloned());
+        // A token must come before the tokens it starts with, the learned ones never start a hard-coded one
+        tokens.sort_by_key(|t| Reverse(t.value.len()));
+        tokens.extend(static_tokens.iter().cloned());
+        Alphabet { symbols, tokens }
+    }
+
+    fn len(&self) -> usize {
+        self.symbols.len()
+    }
+}
+
+fn symbol_index(alphabet: &[Symbol], token: &Token) -> Option<usize> {
+    (0..alphabet.len())
+        .find(|&i| i != EOC_INDEX && i != ESC_INDEX && *alphabet[i].value == *token.value())
+}
+
+fn symbol_frequencies(data: impl Iterator<Item = u8>, alphabet: &Alphabet) -> Vec<u64> {
+    // Occurrences of the symbols in the mapped bytes, the bytes missing from the alphabet count as escapes
+    let mut frequencies = vec![0_u64; alphabet.len()];
+    frequencies[EOC_INDEX] = 1;
+    tokenize(data, &alphabet.tokens)
+        .map(|t| symbol_index(&alphabet.symbols, &t).unwrap_or(ESC_INDEX))
+        .for_each(|i| frequencies[i] += 1);
+    frequencies
+}
+
+fn encode_adaptive(
+    raw_data: &[u8],
+    map: &[u8; 256],
+    alphabet: &Alphabet,
+    indexer: &mut RowIndexer,
+    stats: &EncodingStats,
+) -> io::Result<Vec<u8>> {
+    // Two passes over the data : symbol frequencies first, then encoding with the resulting codebook.
+    // The code lengths are written first, packed two per byte, so that the decoder can rebuild the codes.
+    let frequencies = symbol_frequencies(raw_data.iter().map(|&b| map[usize::from(b)]), alphabet);
+    let lengths = huffman_code_lengths(&frequencies);
+    let mut out = pack_code_lengths(&lengths);
+    out.extend(encode_with_lengths(
+        raw_data, map, alphabet, &lengths, indexer, stats,
+    )?);
+    Ok(out)
+}
+
+fn encode_range(
+    raw_data: &[u8],
+    map: &[u8; 256],
+    alphabet: &Alphabet,
+    indexer: &mut RowIndexer,
+    stats: &EncodingStats,
+) -> Vec<u8> {
+    // Two passes as with the adaptive codebook : the quantized symbol frequencies are written first,
+    // followed by the range coded symbols. Bytes missing from the alphabet are coded after the escape symbol.
+    let freqs = quantize_frequencies(&symbol_frequencies(
+        raw_data.iter().map(|&b| map[usize::from(b)]),
+        alphabet,
+    ));
+    let cumulative = cumulative_frequencies(&freqs);
+    let mut encoder = RangeEncoder::default();
+    for token in tokenize(
+        raw_data.iter().map(|&b| map[usize::from(b)]),
+        &alphabet.tokens,
+    ) {
+        let symbol = symbol_index(&alphabet.symbols, &token).unwrap_or(ESC_INDEX);
+        encoder.encode(cumulative[symbol], u32::from(freqs[symbol]), FREQ_BITS);
+        if let (ESC_INDEX, Token::Byte(b)) = (symbol, &token) {
+            stats.escaped_bytes.fetch_add(1, Ordering::Relaxed);
+            encoder.encode(u32::from(*b), 1, ESCAPED_BYTE_BITS as u32);
+        }
+        if token.value().last() == Some(&b'\n') {
+            indexer.skip_rows(1);
+        }
+    }
+    encoder.encode(
+        cumulative[EOC_INDEX],
+        u32::from(freqs[EOC_INDEX]),
+        FREQ_BITS,
+    );
+    let mut out = pack_frequencies(&freqs);
+    out.extend(encoder.finish());
+    out
+}
+
+fn read_range_frequencies(
+    n_symbols: usize,
+    data: &mut impl Iterator<Item = u8>,
+) -> io::Result<Vec<u16>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let mut freqs = unpack_frequencies(data).ok_or_else(|| invalid("Truncated frequency table"))?;
+    if freqs.len() > n_symbols {
+        return Err(invalid(
+            "Frequency table has more symbols than the alphabet",
+        ));
+    }
+    freqs.resize(n_symbols, 0);
+    if freqs[EOC_INDEX] == 0 || freqs.iter().map(|&f| u32::from(f)).sum::<u32>() != 1 << FREQ_BITS {
+        return Err(invalid("Invalid frequency table"));
+    }
+    Ok(freqs)
+}
+
+fn range_symbols(
+    alphabet: Alphabet,
+    mut data: impl Iterator<Item = u8>,
+) -> impl Iterator<Item = io::Result<Symbol>> {
+    // Consumes exactly the range coded body, the symbols are decoded up to the EOC symbol
+    let mut state = Some(
+        read_range_frequencies(alphabet.len(), &mut data)
+            .and_then(|freqs| Ok((cumulative_frequencies(&freqs), RangeDecoder::new(data)?))),
+    );
+    iter::from_fn(move || {
+        let (cumulative, mut decoder) = match state.take()? {
+            Ok(state) => state,
+            Err(e) => return Some(Err(e)),
+        };
+        let symbol = match decoder.decode(&cumulative) {
+            Ok(EOC_INDEX) => return None,
+            Ok(ESC_INDEX) => decoder
+                .decode_bits(ESCAPED_BYTE_BITS as u32)
+                .map(|b| Symbol::escaped_byte(b as u8)),
+            Ok(i) => Ok(alphabet.symbols[i].clone()),
+            Err(e) => Err(e),
+        };
+        if symbol.is_ok() {
+            state = Some(Ok((cumulative, decoder)));
+        }
+        Some(symbol)
+    })
+}
+
+fn next_context(alphabet: &[Symbol], symbol: usize) -> usize {
+    // Context left by a symbol, rows start in the newline context so that they can be decoded from the row index
+    if alphabet[symbol].value.last() == Some(&b'\n') {
+        NEWLINE_INDEX
+    } else {
+        symbol
+    }
+}
+
+fn context_frequencies(data: impl Iterator<Item = u8>, alphabet: &Alphabet) -> Vec<Vec<u64>> {
+    // Occurrences of the symbols following each symbol of the alphabet, the EOC follows the last one
+    let mut frequencies = vec![vec![0_u64; alphabet.len()]; alphabet.len()];
+    let mut context = NEWLINE_INDEX;
+    tokenize(data, &alphabet.tokens)
+        .map(|t| symbol_index(&alphabet.symbols, &t).unwrap_or(ESC_INDEX))
+        .for_each(|i| {
+            frequencies[context][i] += 1;
+            context = next_context(&alphabet.symbols, i);
+        });
+    frequencies[context][EOC_INDEX] += 1;
+    frequencies
+}
+
+fn encode_context(
+    raw_data: &[u8],
+    map: &[u8; 256],
+    alphabet: &Alphabet,
+    indexer: &mut RowIndexer,
+    stats: &EncodingStats,
+) -> io::Result<Vec<u8>> {
+    // Two passes as with the adaptive codebook, with a code table for each context : the number of tables
+    // is written first, followed by the code lengths of each table without their trailing zeros.
+    // Each symbol is then encoded with the table of the symbol before it.
+    let mapped = || raw_data.iter().map(|&b| map[usize::from(b)]);
+    let lengths = context_frequencies(mapped(), alphabet)
+        .iter()
+        .map(|frequencies| huffman_code_lengths(frequencies))
+        .collect::<Vec<Vec<u8>>>();
+    let mut out = vec![lengths.len() as u8];
+    lengths.iter().for_each(|lengths| {
+        let used = lengths.iter().rposition(|&l| l > 0).map_or(0, |i| i + 1);
+        out.extend(pack_code_lengths(&lengths[..used]))
+    });
+    let codes = lengths
+        .iter()
+        .map(|lengths| canonical_codes(lengths))
+        .collect::<Vec<Vec<Code>>>();
+    let mut context = NEWLINE_INDEX;
+    let mut writer = BitWriter::default();
+    for token in tokenize(mapped(), &alphabet.tokens) {
+        let symbol = symbol_index(&alphabet.symbols, &token).unwrap_or(ESC_INDEX);
+        let code = match (symbol, &token) {
+            (ESC_INDEX, Token::Byte(b)) => {
+                stats.escaped_bytes.fetch_add(1, Ordering::Relaxed);
+                codes[context][symbol].escaped(*b)
+            }
+            _ => codes[context][symbol],
+        };
+        writer.write(code.value, code.len);
+        indexer.push(token.value(), u64::from(code.len));
+        context = next_context(&alphabet.symbols, symbol);
+    }
+    let eoc = codes[context][EOC_INDEX];
+    writer.write(eoc.value, eoc.len);
+    out.extend(writer.finish());
+    Ok(out)
+}
+
+fn read_context_codes(
+    alphabet: Alphabet,
+    data: &mut impl Iterator<Item = u8>,
+) -> io::Result<Vec<DecodingTable>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let n_contexts = data.next().ok_or_else(|| invalid("Truncated code table"))?;
+    if usize::from(n_contexts) != alphabet.len() {
+        return Err(invalid("Code tables don't match the alphabet"));
+    }
+    (0..usize::from(n_contexts))
+        .map(|context| {
+            let mut lengths =
+                unpack_code_lengths(data).ok_or_else(|| invalid("Truncated code table"))?;
+            if lengths.len() > alphabet.len() {
+                return Err(invalid("Code table has more symbols than the alphabet"));
+            }
+            lengths.resize(alphabet.len(), 0);
+            if !is_valid_prefix_code(&lengths) {
+                return Err(invalid("Invalid code table"));
+            }
+            let symbols = canonical_codes(&lengths)
+                .into_iter()
+                .zip(&alphabet.symbols)
+                .enumerate()
+                .map(|(i, (code, symb))| {
+                    let symbol = Symbol {
+                        code,
+                        value: symb.value.clone(),
+                    };
+                    (symbol, next_context(&alphabet.symbols, i))
+                })
+                .collect::<Vec<(Symbol, usize)>>();
+            let eoc = symbols[EOC_INDEX].0.clone();
+            let esc = Some(symbols[ESC_INDEX].0.clone()).filter(|s| !s.code.is_empty());
+            let symbols = symbols
+                .into_iter()
+                .filter(|(s, _)| !s.code.is_empty())
+                .collect::<Vec<(Symbol, usize)>>();
+            Ok(DecodingTable::with_contexts(
+                context,
+                &symbols,
+                &eoc,
+                esc.as_ref(),
+            ))
+        })
+        .collect()
+}
+
+fn encode_with_lengths(
+    raw_data: &[u8],
+    map: &[u8; 256],
+    alphabet: &Alphabet,
+    lengths: &[u8],
+    indexer: &mut RowIndexer,
+    stats: &EncodingStats,
+) -> io::Result<Vec<u8>> {
+    // Encodes with the canonical codes of the given lengths, which are not written.
+    // Symbols without a code are escaped, or rejected when the escape symbol has none either.
+    let codes = canonical_codes(lengths);
+    let mut lookup_table: LookupTable = [None; 256];
+    (0..alphabet.len())
+        .filter(|&i| i != EOC_INDEX && !codes[i].is_empty())
+        .for_each(|i| {
+            if let [b] = *alphabet.symbols[i].value {
+                lookup_table[usize::from(b)] = Some(codes[i]);
+            }
+        });
+    // Tokens without a code are left to their single-byte symbols
+    let tokens = alphabet
+        .tokens
+        .iter()
+        .map(|t| Symbol {
+            code: codes[symbol_index(&alphabet.symbols, &Token::Symbol(t)).unwrap()],
+            value: t.value.clone(),
+        })
+        .filter(|t| !t.code.is_empty())
+        .collect::<Vec<Symbol>>();
+
+    let data = tokenize(raw_data.iter().map(|&b| map[usize::from(b)]), &tokens)
+        .inspect(|t| count_token(indexer, stats, &lookup_table, codes[ESC_INDEX], t));
+    encode_packed(
+        &lookup_table,
+        codes[EOC_INDEX],
+        Some(codes[ESC_INDEX]).filter(|esc| !esc.is_empty()),
+        data,
+    )
+}
+
+fn column_table(column: &[u8], values: &[u8], tokens: &[Vec<u8>]) -> Vec<u8> {
+    // Code lengths fitted to the column, or none when the hard-coded codes are shorter once the table is counted.
+    // Escaped bytes count as escape symbols, coded with the escape code of each codebook. The raw byte
+    // following that code takes as many bits with both and is left out. Every symbol of the given values
+    // gets a code, along with the single-byte symbols of the column, which stand in for the tokens cut
+    // by a block start.
+    let alphabet = Alphabet::new(false, tokens);
+    let static_alphabet = Alphabet::new(false, &[]);
+    let static_frequencies = symbol_frequencies(column.iter().copied(), &static_alphabet);
+    let mut frequencies = symbol_frequencies(column.iter().copied(), &alphabet);
+    [
+        symbol_frequencies(values.iter().copied(), &alphabet),
+        symbol_frequencies(values.iter().copied(), &static_alphabet),
+        static_frequencies.clone(),
+    ]
+    .iter()
+    .for_each(|required| {
+        required
+            .iter()
+            .zip(frequencies.iter_mut())
+            .filter(|(&f, _)| f > 0)
+            .for_each(|(_, f)| *f = (*f).max(1))
+    });
+    let lengths = huffman_code_lengths(&frequencies);
+    let static_lengths = SYMBOLS.iter().map(|s| s.code.len).collect::<Vec<u8>>();
+    let table_bits = 8 * pack_code_lengths(&lengths).len() as u64;
+    if encoded_bits(&frequencies, &lengths) + table_bits
+        < encoded_bits(&static_frequencies, &static_lengths)
+    {
+        lengths
+    } else {
+        Vec::new()
+    }
+}
+
+fn encoded_bits(frequencies: &[u64], lengths: &[u8]) -> u64 {
+    frequencies
+        .iter()
+        .zip(lengths)
+        .map(|(&f, &l)| f * u64::from(l))
+        .sum()
+}
+
+fn fitted_bits(column: &[u8]) -> u64 {
+    // Length of the column encoded with codes fitted to it, escaped bytes left out
+    let frequencies = symbol_frequencies(column.iter().copied(), &Alphabet::new(false, &[]));
+    encoded_bits(&frequencies, &huffman_code_lengths(&frequencies))
+}
+
+fn delta_columns(columns: &[Vec<u8>], delta: &ColumnSelection) -> io::Result<Vec<Option<u8>>> {
+    // Scale of the delta encoded columns, none when no column is
+    let scales = match delta {
+        ColumnSelection::None => Vec::new(),
+        ColumnSelection::Auto => columns
+            .iter()
+            .map(|column| {
+                column_scale(column).filter(|&scale| {
+                    delta_encode(column, scale)
+                        .is_some_and(|deltas| fitted_bits(&deltas) < fitted_bits(column))
+                })
+            })
+            .collect(),
+        ColumnSelection::Columns(indexes) => {
+            let mut scales = vec![None; columns.len()];
+            for &i in indexes {
+                let scale = columns.get(i).and_then(|column| column_scale(column));
+                scales[i] = Some(scale.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "The column {} can't be delta encoded : it must hold integers or decimals with a fixed number of digits",
+                            i
+                        ),
+                    )
+                })?);
+            }
+            scales
+        }
+    };
+    Ok(if scales.iter().all(Option::is_none) {
+        Vec::new()
+    } else {
+        scales
+    })
+}
+
+fn delta_transform(columns: Vec<Vec<u8>>, metadata: &Metadata) -> io::Result<Vec<Vec<u8>>> {
+    // Replaces the values of the delta encoded columns with their differences
+    columns
+        .into_iter()
+        .enumerate()
+        .map(|(i, column)| match metadata.delta_columns.get(i) {
+            Some(&Some(scale)) => delta_encode(&column, scale).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("The column {} can't be delta encoded", i),
+                )
+            }),
+            _ => Ok(column),
+        })
+        .collect()
+}
+
+fn has_value_encoding(metadata: &Metadata, column: usize) -> bool {
+    // Whether the values of the column are already delta encoded or stored as scaled integers
+    matches!(metadata.delta_columns.get(column), Some(Some(_)))
+        || matches!(metadata.scaled_columns.get(column), Some(Some(_)))
+}
+
+fn value_encoding_error(column: usize, encoding: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "The column {} can't be {} : it must exist and not have another encoding",
+            column, encoding
+        ),
+    )
+}
+
+fn scaled_columns(
+    columns: &[Vec<u8>],
+    scaled: &ColumnSelection,
+    metadata: &Metadata,
+) -> io::Result<Vec<Option<u8>>> {
+    // Scale of the columns stored as scaled integers among the columns that aren't delta encoded
+    let scales = match scaled {
+        ColumnSelection::None => Vec::new(),
+        ColumnSelection::Auto => columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                let scale = detect_scale(column);
+                let (section, text) = scaled_encode(column, scale);
+                (!has_value_encoding(metadata, i)
+                    && 8 * section.len() as u64 + fitted_bits(&text) < fitted_bits(column))
+                .then_some(scale)
+            })
+            .collect(),
+        ColumnSelection::Columns(indexes) => {
+            let mut scales = vec![None; columns.len()];
+            for &i in indexes {
+                if i >= columns.len() || has_value_encoding(metadata, i) {
+                    return Err(value_encoding_error(i, "stored as scaled integers"));
+                }
+                scales[i] = Some(detect_scale(&columns[i]));
+            }
+            scales
+        }
+    };
+    Ok(if scales.iter().all(Option::is_none) {
+        Vec::new()
+    } else {
+        scales
+    })
+}
+
+fn xor_columns(
+    columns: &[Vec<u8>],
+    xor_floats: &ColumnSelection,
+    metadata: &Metadata,
+) -> io::Result<Vec<bool>> {
+    // Columns whose floats are XOR encoded among the columns without another encoding
+    let is_encoded = |i: usize| has_value_encoding(metadata, i);
+    let columns = match xor_floats {
+        ColumnSelection::None => Vec::new(),
+        ColumnSelection::Auto => columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                let (section, text) = xor_encode(column);
+                !is_encoded(i)
+                    && 8 * section.len() as u64 + fitted_bits(&text) < fitted_bits(column)
+            })
+            .collect(),
+        ColumnSelection::Columns(indexes) => {
+            let mut xor = vec![false; columns.len()];
+            for &i in indexes {
+                if i >= columns.len() || is_encoded(i) {
+                    return Err(value_encoding_error(i, "XOR encoded"));
+                }
+                xor[i] = true;
+            }
+            xor
+        }
+    };
+    Ok(if columns.iter().all(|&xor| !xor) {
+        Vec::new()
+    } else {
+        columns
+    })
+}
+
+fn column_streams(
+    columns: Vec<Vec<u8>>,
+    metadata: &Metadata,
+) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    // Value section (if any) and text of each column, the text being encoded with the codebook
+    Ok(delta_transform(columns, metadata)?
+        .into_iter()
+        .enumerate()
+        .map(
+            |(i, column)| match (metadata.xor_columns.get(i), metadata.scaled_columns.get(i)) {
+                (Some(true), _) => xor_encode(&column),
+                (_, Some(&Some(scale))) => scaled_encode(&column, scale),
+                _ => (Vec::new(), column),
+            },
+        )
+        .collect())
+}
+
+fn fit_columns(
+    metadata: &mut Metadata,
+    raw_data: &[u8],
+    options: &EncodingOptions,
+) -> io::Result<()> {
+    // Chooses the delta and XOR encoded columns, then the code tables of the resulting text columns
+    let map = metadata.encoding_map();
+    let mapped = raw_data
+        .iter()
+        .map(|&b| map[usize::from(b)])
+        .collect::<Vec<u8>>();
+    let columns = transpose(&mapped).1;
+    metadata.delta_columns = delta_columns(&columns, &options.delta)?;
+    metadata.scaled_columns = scaled_columns(&columns, &options.scaled, metadata)?;
+    metadata.xor_columns = xor_columns(&columns, &options.xor_floats, metadata)?;
+    if !options.tokens && options.codebook != Codebook::PerColumn {
+        return Ok(());
+    }
+    let texts = column_streams(columns.clone(), metadata)?
+        .into_iter()
+        .map(|(_, text)| text)
+        .collect::<Vec<Vec<u8>>>();
+    if options.tokens {
+        metadata.tokens = learn_tokens(&texts, false, options.codebook);
+    }
+    if options.codebook == Codebook::PerColumn {
+        // Blocks start delta encoded columns with a whole value, their symbols need a code
+        metadata.column_tables = texts
+            .iter()
+            .zip(&columns)
+            .enumerate()
+            .map(|(i, (text, values))| match metadata.delta_columns.get(i) {
+                Some(Some(_)) => column_table(text, values, &metadata.tokens),
+                _ => column_table(text, text, &metadata.tokens),
+            })
+            .collect();
+    }
+    Ok(())
+}
+
+fn token_candidates(texts: &[Vec<u8>], crlf: bool) -> Vec<Vec<u8>> {
+    // Most frequent runs of 2 or 3 single-byte symbols, weighted by the symbols they save.
+    // A newline only ends a token so that rows still start on a symbol, carriage returns are left out.
+    let mut is_symbol = [false; 256];
+    Alphabet::new(crlf, &[])
+        .symbols
+        .iter()
+        .filter_map(|s| match *s.value {
+            [b] if b != b'\r' => Some(b),
+            _ => None,
+        })
+        .for_each(|b| is_symbol[usize::from(b)] = true);
+    let mut counts = HashMap::<&[u8], u64>::new();
+    for text in texts {
+        let sample = &text[..text.len().min(TOKEN_SAMPLE_LEN)];
+        (2..=3)
+            .flat_map(|len| sample.windows(len))
+            .filter(|run| {
+                run.iter().all(|&b| is_symbol[usize::from(b)])
+                    && !run[..run.len() - 1].contains(&b'\n')
+            })
+            .for_each(|run| *counts.entry(run).or_default() += 1);
+    }
+    let mut candidates = counts
+        .into_iter()
+        .map(|(run, count)| (count * (run.len() as u64 - 1), run))
+        .collect::<Vec<(u64, &[u8])>>();
+    candidates.sort_unstable_by(|a, b| b.cmp(a));
+    candidates
+        .into_iter()
+        .take(MAX_TOKEN_CANDIDATES)
+        .map(|(_, run)| run.to_vec())
+        .collect()
+}
+
+fn tokens_bits(texts: &[Vec<u8>], crlf: bool, tokens: &[Vec<u8>], codebook: Codebook) -> u64 {
+    // Estimated length of the texts encoded with codes fitted to each of them, code tables and tokens included.
+    // The context codebook has a code table for each context.
+    let alphabet = Alphabet::new(crlf, tokens);
+    let texts_bits = texts
+        .iter()
+        .map(|text| {
+            let sample = text[..text.len().min(TOKEN_SAMPLE_LEN)].iter().copied();
+            let tables = match codebook {
+                Codebook::Context => context_frequencies(sample, &alphabet),
+                _ => vec![symbol_frequencies(sample, &alphabet)],
+            };
+            tables
+                .iter()
+                .map(|frequencies| {
+                    let lengths = huffman_code_lengths(frequencies);
+                    let used = lengths.iter().rposition(|&l| l > 0).map_or(0, |i| i + 1);
+                    encoded_bits(frequencies, &lengths)
+                        + 8 * pack_code_lengths(&lengths[..used]).len() as u64
+                })
+                .sum::<u64>()
+        })
+        .sum::<u64>();
+    texts_bits + 8 * tokens.iter().map(|t| t.len() as u64 + 1).sum::<u64>()
+}
+
+fn learn_tokens(texts: &[Vec<u8>], crlf: bool, codebook: Codebook) -> Vec<Vec<u8>> {
+    // The candidates are tried one after the other, and kept when they shorten the estimated encoding
+    let mut tokens = Vec::new();
+    let mut bits = tokens_bits(texts, crlf, &tokens, codebook);
+    for candidate in token_candidates(texts, crlf) {
+        if tokens.len() == MAX_TOKENS {
+            break;
+        }
+        tokens.push(candidate);
+        let candidate_bits = tokens_bits(texts, crlf, &tokens, codebook);
+        if candidate_bits < bits {
+            bits = candidate_bits;
+        } else {
+            tokens.pop();
+        }
+    }
+    tokens
+}
+
+fn fit_rows(metadata: &mut Metadata, raw_data: &[u8], crlf: bool, codebook: Codebook) {
+    // Learns the tokens of a row-major body
+    let map = metadata.encoding_map();
+    let mapped = raw_data
+        .iter()
+        .map(|&b| map[usize::from(b)])
+        .collect::<Vec<u8>>();
+    metadata.tokens = learn_tokens(&[mapped], crlf, codebook);
+}
+
+fn tokens_codebook_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "Multi-byte tokens need a codebook fitted to the input",
+    )
+}
+
+fn read_adaptive_symbols(
+    alphabet: Vec<Symbol>,
+    data: &mut impl Iterator<Item = u8>,
+) -> io::Result<DecodingCodes> {
+    let lengths = unpack_code_lengths(data)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Truncated code table"))?;
+    adaptive_symbols(alphabet, lengths)
+}
+
+fn adaptive_symbols(alphabet: Vec<Symbol>, mut lengths: Vec<u8>) -> io::Result<DecodingCodes> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    if lengths.len() > alphabet.len() {
+        return Err(invalid("Code table has more symbols than the alphabet"));
+    }
+    lengths.resize(alphabet.len(), 0);
+    if lengths[EOC_INDEX] == 0 || !is_valid_prefix_code(&lengths) {
+        return Err(invalid("Invalid code table"));
+    }
+    let symbols = canonical_codes(&lengths)
+        .into_iter()
+        .zip(alphabet)
+        .map(|(code, symb)| Symbol {
+            code,
+            value: symb.value,
+        })
+        .collect::<Vec<Symbol>>();
+    let eoc = symbols[EOC_INDEX].clone();
+    let esc = Some(symbols[ESC_INDEX].clone()).filter(|s| !s.code.is_empty());
+    Ok((
+        symbols.into_iter().filter(|s| !s.code.is_empty()).collect(),
+        eoc,
+        esc,
+    ))
+}
+
+// Symbols, EOC symbol and escape symbol (if any) of a codebook
+type DecodingCodes = (Vec<Symbol>, Symbol, Option<Symbol>);
+
+enum PrefixCodes {
+    Single(DecodingCodes),
+    // Codes of each context of the context codebook, rows starting in the newline context
+    Contexts(Vec<DecodingTable>),
+}
+
+fn read_symbols(
+    header: Header,
+    metadata: &Metadata,
+    data: &mut impl Iterator<Item = u8>,
+) -> io::Result<PrefixCodes> {
+    // The learned tokens only belong to the fitted codebooks
+    let crlf = header.flags & FLAG_CRLF != 0;
+    let alphabet = || Alphabet::new(crlf, &metadata.tokens);
+    match header.codebook {
+        Codebook::Utf8Numeric => Ok(PrefixCodes::Single((
+            NUMERIC_SYMBOLS.to_vec(),
+            SYMB_NUMERIC_EOC,
+            None,
+        ))),
+        Codebook::Utf8Static => Ok(PrefixCodes::Single((
+            Alphabet::new(crlf, &[]).symbols,
+            SYMB_EOC,
+            Some(SYMB_ESC),
+        ))),
+        Codebook::Adaptive => {
+            read_adaptive_symbols(alphabet().symbols, data).map(PrefixCodes::Single)
+        }
+        Codebook::Context => read_context_codes(alphabet(), data).map(PrefixCodes::Contexts),
+        Codebook::PerColumn => Err(per_column_codebook_error()),
+        Codebook::Range => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The range codebook has no prefix codes",
+        )),
+    }
+}
+
+fn decode_stream(
+    header: Header,
+    metadata: &Metadata,
+    mut data: impl Iterator<Item = u8>,
+    bit_offset: u64,
+) -> impl Iterator<Item = io::Result<u8>> {
+    // Rows of a row-major body, the prefix codes being read from the given bit offset
+    if header.codebook == Codebook::Range {
+        let map = metadata.decoding_map();
+        return Either::Left(
+            symbol_bytes(range_symbols(
+                Alphabet::new(header.flags & FLAG_CRLF != 0, &metadata.tokens),
+                data,
+            ))
+            .map(move |b| b.map(|b| map[usize::from(b)])),
+        );
+    }
+    Either::Right(match read_symbols(header, metadata, &mut data) {
+        Ok(codes) => Either::Left(decode_codes(
+            codes,
+            metadata,
+            BitReader::new(data).skip(bit_offset),
+        )),
+        Err(e) => Either::Right(iter::once(Err(e))),
+    })
+}
+
+fn decode_stream_into(
+    header: Header,
+    metadata: &Metadata,
+    mut data: impl Iterator<Item = u8>,
+    bit_offset: u64,
+    lookahead: u32,
+    out: &mut Vec<u8>,
+) -> io::Result<Vec<u8>> {
+    // Same as `decode_stream`, the rows are appended to the buffer.
+    // The prefix codes may read up to `lookahead` bytes past the body, they are given back.
+    let start = out.len();
+    let decoded = if header.codebook == Codebook::Range {
+        symbol_bytes_into(
+            range_symbols(
+                Alphabet::new(header.flags & FLAG_CRLF != 0, &metadata.tokens),
+                data,
+            ),
+            out,
+        )
+        .map(|()| Vec::new())
+    } else {
+        read_symbols(header, metadata, &mut data).and_then(|codes| {
+            let bits = BitReader::with_lookahead(data, lookahead).skip(bit_offset);
+            code_symbols(codes, bits).decode_into(out)
+        })
+    };
+    let map = metadata.decoding_map();
+    out[start..]
+        .iter_mut()
+        .for_each(|b| *b = map[usize::from(*b)]);
+    decoded
+}
+
+fn code_symbols<I: Iterator<Item = u8>>(
+    codes: PrefixCodes,
+    bits: BitReader<I>,
+) -> DecoderUnfold<I> {
+    // Rows start in the newline context, a single codebook has no other
+    match codes {
+        PrefixCodes::Single((symbols, eoc, esc)) => {
+            DecoderUnfold::new(bits, DecodingTable::new(&symbols, &eoc, esc.as_ref()))
+        }
+        PrefixCodes::Contexts(tables) => DecoderUnfold::with_contexts(bits, tables, NEWLINE_INDEX),
+    }
+}
+
+fn decode_codes(
+    codes: PrefixCodes,
+    metadata: &Metadata,
+    bits: BitReader<impl Iterator<Item = u8>>,
+) -> impl Iterator<Item = io::Result<u8>> {
+    // The codebooks only know `,` and `.`, the decoded bytes go back to the delimiter and decimal mark of the csv
+    let map = metadata.decoding_map();
+    symbol_bytes(code_symbols(codes, bits)).map(move |b| b.map(|b| map[usize::from(b)]))
+}
+
+fn count_token(
+    indexer: &mut RowIndexer,
+    stats: &EncodingStats,
+    lookup_table: &LookupTable,
+    esc_code: Code,
+    token: &Token,
+) {
+    // Feeds the row indexer with the encoded length of the token
+    let bit_len = match token.code(lookup_table) {
+        Some(code) => usize::from(code.len),
+        None => {
+            stats.escaped_bytes.fetch_add(1, Ordering::Relaxed);
+            usize::from(esc_code.len) + ESCAPED_BYTE_BITS
+        }
+    };
+    indexer.push(token.value(), bit_len as u64);
+}
+
+fn static_codes(crlf: bool) -> (&'static LookupTable, &'static [Symbol], Code) {
+    // Lookup table, multi-byte symbols and EOC code of the hard-coded codebook
+    if crlf {
+        (
+            &UTF_8_CRLF_LOOKUP_ENCODING_TABLE,
+            &CRLF_TOKENS,
+            SYMB_EOC.code,
+        )
+    } else {
+        (&UTF_8_LOOKUP_ENCODING_TABLE, &TOKENS, SYMB_EOC.code)
+    }
+}
+
+fn encode_body(
+    header: Header,
+    metadata: &Metadata,
+    raw_data: &[u8],
+    index_interval: Option<u64>,
+    stats: &EncodingStats,
+) -> io::Result<(Vec<u8>, RowIndexer)> {
+    if header.flags & FLAG_COLUMNAR != 0 {
+        return encode_columnar(header, metadata, raw_data, stats);
+    }
+    let map = &metadata.encoding_map();
+    // Without index, the indexer only counts the rows
+    let mut indexer = RowIndexer::new(index_interval.unwrap_or(u64::MAX));
+    let crlf = header.flags & FLAG_CRLF != 0;
+    let alphabet = &Alphabet::new(crlf, &metadata.tokens);
+    let body = match header.codebook {
+        Codebook::Utf8Numeric => return Err(numeric_codebook_error()),
+        Codebook::Utf8Static => {
+            let (lookup_table, tokens, eoc_code) = static_codes(crlf);
+            let data = tokenize(raw_data.iter().map(|&b| map[usize::from(b)]), tokens)
+                .inspect(|t| count_token(&mut indexer, stats, lookup_table, SYMB_ESC.code, t));
+            encode_packed(lookup_table, eoc_code, Some(SYMB_ESC.code), data)?
+        }
+        Codebook::Adaptive => encode_adaptive(raw_data, map, alphabet, &mut indexer, stats)?,
+        Codebook::PerColumn => return Err(per_column_codebook_error()),
+        Codebook::Context => encode_context(raw_data, map, alphabet, &mut indexer, stats)?,
+        Codebook::Range => {
+            // Rows can't be found back inside the range coded symbols, the index only points to the start of the body
+            indexer = RowIndexer::new(u64::MAX);
+            encode_range(raw_data, map, alphabet, &mut indexer, stats)
+        }
+    };
+    Ok((body, indexer))
+}
+
+fn encode_columnar(
+    header: Header,
+    metadata: &Metadata,
+    raw_data: &[u8],
+    stats: &EncodingStats,
+) -> io::Result<(Vec<u8>, RowIndexer)> {
+    // Each column is encoded as a row-major stream of its values.
+    // Rows can't be found back inside the columns, the index only points to the start of the body.
+    let map = metadata.encoding_map();
+    let mapped = raw_data
+        .iter()
+        .map(|&b| map[usize::from(b)])
+        .collect::<Vec<u8>>();
+    let (shapes, columns) = transpose(&mapped);
+    let encoded_columns = column_streams(columns, metadata)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, (mut encoded, text))| {
+            encoded.extend(match column_codebook(header, metadata, i)? {
+                (_, Some(lengths)) => {
+                    let alphabet = Alphabet::new(false, &metadata.tokens);
+                    let mut indexer = RowIndexer::new(u64::MAX);
+                    encode_with_lengths(
+                        &text,
+                        &BYTE_VALUES,
+                        &alphabet,
+                        lengths,
+                        &mut indexer,
+                        stats,
+                    )?
+                }
+                (row_major, None) => {
+                    encode_body(row_major, &column_metadata(metadata), &text, None, stats)?.0
+                }
+            });
+            Ok(encoded)
+        })
+        .collect::<io::Result<Vec<Vec<u8>>>>()?;
+    let mut indexer = RowIndexer::new(u64::MAX);
+    indexer.skip_rows(count_rows(&shapes));
+    Ok((write_columnar(&shapes, &encoded_columns), indexer))
+}
+
+fn column_metadata(metadata: &Metadata) -> Metadata {
+    // Metadata of the row-major streams of the columns, which only keep the learned tokens
+    Metadata {
+        tokens: metadata.tokens.clone(),
+        ..Metadata::default()
+    }
+}
+
+fn column_codebook(
+    header: Header,
+    metadata: &Metadata,
+    column: usize,
+) -> io::Result<(Header, Option<&[u8]>)> {
+    // Row-major header of a column, along with its code lengths when it has its own table
+    let row_major = Header {
+        flags: header.flags & !FLAG_COLUMNAR,
+        ..header
+    };
+    if header.codebook != Codebook::PerColumn {
+        return Ok((row_major, None));
+    }
+    let static_header = Header {
+        codebook: Codebook::Utf8Static,
+        ..row_major
+    };
+    match metadata.column_tables.get(column) {
+        Some(lengths) if lengths.is_empty() => Ok((static_header, None)),
+        Some(lengths) => Ok((row_major, Some(lengths))),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Missing code table of the column {}", column),
+        )),
+    }
+}
+
+fn per_column_codebook_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "The per-column codebook needs a columnar body",
+    )
+}
+
+fn numeric_codebook_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "The digits-only codebook is only kept to decode older files",
+    )
+}
+
+pub(crate) fn decode_body(
+    header: Header,
+    metadata: &Metadata,
+    payload: &[u8],
+    bit_offset: u64,
+) -> io::Result<Vec<u8>> {
+    let mut data = payload.iter().copied();
+    if header.flags & FLAG_COLUMNAR != 0 {
+        return decode_columnar(header, metadata, &mut data);
+    }
+    // Nothing follows the payload, the decoder can read ahead as much as it wants
+    let mut rows = Vec::new();
+    decode_stream_into(header, metadata, data, bit_offset, u32::MAX, &mut rows)?;
+    Ok(rows)
+}
+
+fn decode_columnar(
+    header: Header,
+    metadata: &Metadata,
+    data: &mut impl Iterator<Item = u8>,
+) -> io::Result<Vec<u8>> {
+    // Consumes exactly the columnar body, the rows are rebuilt once every column is decoded
+    let (shapes, encoded_columns) = read_columnar(data)?;
+    let columns = encoded_columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            let is_xor = metadata.xor_columns.get(i) == Some(&true);
+            let scale = metadata.scaled_columns.get(i).copied().flatten();
+            let (section, column) = if is_xor || scale.is_some() {
+                split_value_section(column)?
+            } else {
+                ((0, &[][..]), &column[..])
+            };
+            let text = match column_codebook(header, metadata, i)? {
+                (_, Some(lengths)) => {
+                    let mut text = Vec::new();
+                    code_symbols(
+                        PrefixCodes::Single(adaptive_symbols(
+                            Alphabet::new(false, &metadata.tokens).symbols,
+                            lengths.to_vec(),
+                        )?),
+                        BitReader::with_lookahead(column.iter().copied(), u32::MAX),
+                    )
+                    .decode_into(&mut text)?;
+                    text
+                }
+                (row_major, None) => decode_body(row_major, &column_metadata(metadata), column, 0)?,
+            };
+            let column = match (is_xor, scale) {
+                (true, _) => xor_decode(section, &text)?,
+                (_, Some(scale)) => scaled_decode(section, &text, scale)?,
+                _ => text,
+            };
+            match metadata.delta_columns.get(i) {
+                Some(&Some(scale)) => delta_decode(&column, scale),
+                _ => Ok(column),
+            }
+        })
+        .collect::<io::Result<Vec<Vec<u8>>>>()?;
+    let map = metadata.decoding_map();
+    Ok(untranspose(&shapes, &columns)?
+        .into_iter()
+        .map(|b| map[usize::from(b)])
+        .collect())
+}
+
+fn index_footer(
+    interval: u64,
+    body_offset: u64,
+    footer_offset: u64,
+    indexer: &RowIndexer,
+) -> Vec<u8> {
+    // Index of a stream without blocks, all the entries point to the start of the body
+    RowIndex {
+        interval,
+        entries: indexer
+            .offsets
+            .iter()
+            .map(|&(row, bit)| IndexEntry {
+                row,
+                block_offset: body_offset,
+                bit_offset: bit,
+            })
+            .collect(),
+        rows: indexer.rows(),
+        footer_offset,
+    }
+    .to_bytes()
+}
+
+fn is_encodable(line: &[u8], map: &[u8; 256]) -> bool {
+    // Whether the hard-coded codebook has a code for every symbol of the line
+    let (lookup_table, tokens, _) = static_codes(false);
+    tokenize(line.iter().map(|&b| map[usize::from(b)]), tokens)
+        .all(|t| t.code(lookup_table).is_some())
+}
+
+pub(crate) fn read_stream_start(
+    data: &mut impl Iterator<Item = u8>,
+) -> io::Result<(Header, Metadata, u64)> {
+    // Header and metadata section, along with the offset of the body
+    let header = Header::read(data)?;
+    if header.flags & FLAG_METADATA == 0 {
+        return Ok((header, Metadata::default(), HEADER_LEN as u64));
+    }
+    let (metadata, metadata_len) = Metadata::read(data)?;
+    Ok((header, metadata, (HEADER_LEN + metadata_len) as u64))
+}
+
+fn decode_frame(
+    options: &DecodingOptions,
+    data: Rc<RefCell<impl Iterator<Item = u8>>>,
+    frame_start: u64,
+    position: Rc<Cell<u64>>,
+) -> impl Iterator<Item = io::Result<u8>> {
+    // Header row and rows of a single stream, followed by its checksum and row index footer (if any).
+    // The stream starts at `frame_start` in the input, `position` counts the bytes read from the input.
+    let reader = data.clone();
+    let mut body = iter::from_fn(move || reader.borrow_mut().next());
+    let (header, metadata, body_offset) = match read_stream_start(&mut body) {
+        Ok(start) => start,
+        // A bad header or metadata section is reported as the first (and only) item of the stream
+        Err(e) => return Either::Right(iter::once(Err(e))),
+    };
+    let body_start = frame_start + body_offset;
+    // The header row is emitted before the decoded rows
+    let header_row = metadata
+        .header_row
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .map(Ok);
+    let rows = if header.flags & FLAG_BLOCKS != 0 {
+        Either::Left(decode_blocks(
+            body,
+            options.jobs,
+            body_start,
+            header.flags & FLAG_CHECKSUM != 0,
+            move |payload| decode_body(header, &metadata, payload, 0),
+        ))
+    } else {
+        // The decoder stops on the byte holding the EOC symbol, or at the end of the columnar body,
+        // the csv checksum comes right after
+        let decoded = if header.flags & FLAG_COLUMNAR != 0 {
+            Either::Left(
+                iter::once_with(move || decode_columnar(header, &metadata, &mut body)).flat_map(
+                    |rows| match rows {
+                        Ok(rows) => Either::Left(rows.into_iter().map(Ok)),
+                        Err(e) => Either::Right(iter::once(Err(e))),
+                    },
+                ),
+            )
+        } else {
+            Either::Right(decode_stream(header, &metadata, body, 0))
+        };
+        if header.flags & FLAG_CHECKSUM != 0 {
+            let (reader, position) = (data.clone(), position.clone());
+            Either::Right(Either::Left(ChecksumVerifier::new(
+                decoded,
+                move |actual| {
+                    let body_end = position.get();
+                    let expected = read_checksum(&mut *reader.borrow_mut())?;
+                    verify_checksum(actual, expected, body_start..body_end)
+                },
+            )))
+        } else {
+            Either::Right(Either::Right(decoded))
+        }
+    };
+    let footer = iter::once_with(move || match header.flags & FLAG_INDEX {
+        0 => None,
+        _ => RowIndex::read(&mut *data.borrow_mut(), position.get() - frame_start).err(),
+    })
+    .flatten()
+    .map(Err);
+    Either::Left(header_row.chain(rows).chain(footer))
+}
+
+fn start_stream(
+    options: &EncodingOptions,
+    raw_data: impl Iterator<Item = u8>,
+) -> io::Result<(Header, Metadata, impl Iterator<Item = u8>)> {
+    // Header and metadata of the stream, fitted to the input when needed, along with the rows to encode
+    let codebook = options.codebook;
+    let index_interval = options.index_interval;
+    let mut metadata = Metadata {
+        delimiter: options.delimiter,
+        decimal_mark: options.decimal_mark,
+        block_size: options.block_size,
+        ..Metadata::default()
+    };
+    let map = metadata.encoding_map();
+    let mut raw_data = raw_data;
+    let first_line = read_first_line(&mut raw_data);
+    // Columns don't hold the line endings, carriage returns stay at the end of the last values
+    let fitted_columns = codebook == Codebook::PerColumn
+        || options.delta != ColumnSelection::None
+        || options.xor_floats != ColumnSelection::None
+        || options.scaled != ColumnSelection::None;
+    let columnar = options.columnar || fitted_columns;
+    let crlf =
+        options.line_endings == LineEndings::Preserve && !columnar && first_line.ends_with(b"\r\n");
+    let is_header_row = match options.header_row {
+        HeaderRow::Detect => !is_encodable(&first_line, &map),
+        HeaderRow::Present => true,
+        HeaderRow::Absent => false,
+    };
+    let (header_row, raw_data) = if is_header_row {
+        (Some(first_line), Either::Left(raw_data))
+    } else {
+        (None, Either::Right(first_line.into_iter().chain(raw_data)))
+    };
+    let (header_row, raw_data) = match options.line_endings {
+        LineEndings::Preserve => (header_row, Either::Left(raw_data)),
+        LineEndings::Normalize => {
+            let stats = options.stats.clone();
+            let on_normalized = move || {
+                stats
+                    .normalized_line_endings
+                    .fetch_add(1, Ordering::Relaxed);
+            };
+            let header_row = header_row.map(|line| {
+                normalize_line_endings(line.into_iter(), on_normalized.clone()).collect()
+            });
+            (
+                header_row,
+                Either::Right(normalize_line_endings(raw_data, on_normalized)),
+            )
+        }
+    };
+    // The header row is stored as is, a detected one only has to be a line the codebook can't encode
+    let invalid_header_row = options.header_row == HeaderRow::Present
+        && header_row
+            .as_ref()
+            .is_some_and(|line| std::str::from_utf8(line).is_err());
+    metadata.header_row = header_row;
+    let mut flags = 0;
+    if options.block_size.is_some() {
+        flags |= FLAG_BLOCKS;
+    }
+    if index_interval.is_some() {
+        flags |= FLAG_INDEX;
+    }
+    let checksum = options.checksum;
+    if checksum {
+        flags |= FLAG_CHECKSUM;
+    }
+    if crlf {
+        flags |= FLAG_CRLF;
+    }
+    if options.line_endings == LineEndings::Normalize {
+        flags |= FLAG_NORMALIZED_EOL;
+    }
+    if columnar {
+        flags |= FLAG_COLUMNAR;
+    }
+    // The encodings of the columns and the tokens are fitted to the whole input before anything is written
+    let (raw_data, fitted) = if fitted_columns || options.tokens {
+        let raw_data = raw_data.collect::<Vec<u8>>();
+        let fitted = match codebook {
+            Codebook::Utf8Static | Codebook::Utf8Numeric if options.tokens => {
+                Err(tokens_codebook_error())
+            }
+            _ if columnar => fit_columns(&mut metadata, &raw_data, options),
+            _ => {
+                fit_rows(&mut metadata, &raw_data, crlf, codebook);
+                Ok(())
+            }
+        };
+        (Either::Left(raw_data.into_iter()), fitted)
+    } else {
+        (Either::Right(raw_data), Ok(()))
+    };
+    if !metadata.is_default() {
+        flags |= FLAG_METADATA;
+    }
+    let header = Header {
+        flags,
+        ..Header::new(codebook)
+    };
+    if invalid_header_row {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The header row is not valid UTF-8",
+        ));
+    }
+    fitted.and_then(|()| metadata.validate())?;
+    Ok((header, metadata, raw_data))
+}
+
+fn stream_start_bytes(header: Header, metadata: &Metadata) -> Vec<u8> {
+    // Header followed by the metadata section, when the stream has one
+    let mut bytes = header.to_bytes().to_vec();
+    if header.flags & FLAG_METADATA != 0 {
+        bytes.extend(metadata.to_bytes(header.flags & FLAG_CHECKSUM != 0));
+    }
+    bytes
+}
+
+fn encode_rows(
+    header: Header,
+    metadata: &Metadata,
+    raw_data: &[u8],
+    options: &EncodingOptions,
+    body_offset: u64,
+) -> io::Result<Vec<u8>> {
+    // Body of a stream without blocks, followed by the csv checksum and the row index footer (if any)
+    let (mut body, indexer) = encode_body(
+        header,
+        metadata,
+        raw_data,
+        options.index_interval,
+        &options.stats,
+    )?;
+    if options.checksum {
+        body.extend(crc32(raw_data).to_le_bytes());
+    }
+    if let Some(interval) = options.index_interval {
+        let footer_offset = body_offset + body.len() as u64;
+        body.extend(index_footer(interval, body_offset, footer_offset, &indexer));
+    }
+    Ok(body)
+}
+
+pub(crate) struct RowEncoder {
+    // Hard-coded codebook stream whose rows are encoded as they are written, see `BncsvEncoder`
+    lookup_table: &'static LookupTable,
+    tokens: &'static [Symbol],
+    map: [u8; 256],
+    indexer: RowIndexer,
+    crc: Crc32,
+    bits: BitWriter,
+    body_offset: u64,
+    // Bytes of the stream returned so far
+    offset: u64,
+}
+
+impl RowEncoder {
+    pub(crate) fn start(
+        options: &EncodingOptions,
+        rows: &[u8],
+    ) -> io::Result<(RowEncoder, Vec<u8>)> {
+        // Stream start and first rows, the header row and the line endings are detected on their first line
+        let (header, metadata, raw_data) = start_stream(options, rows.iter().copied())?;
+        let mut bytes = stream_start_bytes(header, &metadata);
+        let (lookup_table, tokens, _) = static_codes(header.flags & FLAG_CRLF != 0);
+        let mut encoder = RowEncoder {
+            lookup_table,
+            tokens,
+            map: metadata.encoding_map(),
+            indexer: RowIndexer::new(options.index_interval.unwrap_or(u64::MAX)),
+            crc: Crc32::new(),
+            bits: BitWriter::default(),
+            body_offset: bytes.len() as u64,
+            offset: 0,
+        };
+        encoder.encode(raw_data, &options.stats)?;
+        bytes.extend(encoder.bits.take_bytes());
+        encoder.offset = bytes.len() as u64;
+        Ok((encoder, bytes))
+    }
+
+    pub(crate) fn push(&mut self, options: &EncodingOptions, rows: &[u8]) -> io::Result<Vec<u8>> {
+        // Whole bytes of the following rows, the bits of an incomplete byte wait for the next ones
+        let rows = rows.iter().copied();
+        match options.line_endings {
+            LineEndings::Preserve => self.encode(rows, &options.stats)?,
+            LineEndings::Normalize => {
+                let stats = options.stats.clone();
+                let rows = normalize_line_endings(rows, move || {
+                    stats
+                        .normalized_line_endings
+                        .fetch_add(1, Ordering::Relaxed);
+                });
+                self.encode(rows, &options.stats)?
+            }
+        }
+        let bytes = self.bits.take_bytes();
+        self.offset += bytes.len() as u64;
+        Ok(bytes)
+    }
+
+    fn encode(
+        &mut self,
+        raw_data: impl Iterator<Item = u8>,
+        stats: &EncodingStats,
+    ) -> io::Result<()> {
+        let RowEncoder {
+            lookup_table,
+            tokens,
+            map,
+            indexer,
+            crc,
+            bits,
+            ..
+        } = self;
+        let raw_data = raw_data
+            .inspect(|&b| crc.push(b))
+            .map(|b| map[usize::from(b)]);
+        for token in tokenize(raw_data, tokens) {
+            count_token(indexer, stats, lookup_table, SYMB_ESC.code, &token);
+            let code = token_code(lookup_table, Some(SYMB_ESC.code), &token)?;
+            bits.write(code.value, code.len);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self, options: &EncodingOptions) -> Vec<u8> {
+        // EOC symbol and padding, followed by the csv checksum and the row index footer (if any)
+        self.bits.write(SYMB_EOC.code.value, SYMB_EOC.code.len);
+        let mut bytes = self.bits.finish();
+        if options.checksum {
+            bytes.extend(self.crc.value().to_le_bytes());
+        }
+        if let Some(interval) = options.index_interval {
+            let footer_offset = self.offset + bytes.len() as u64;
+            bytes.extend(index_footer(
+                interval,
+                self.body_offset,
+                footer_offset,
+                &self.indexer,
+            ));
+        }
+        bytes
+    }
+}
+
+fn decode_frame_into(
+    options: &DecodingOptions,
+    data: &mut impl Iterator<Item = u8>,
+    frame_start: u64,
+    out: &mut Vec<u8>,
+) -> io::Result<()> {
+    // Same as `decode_frame`, the whole stream is decoded before its checksum is verified.
+    // Without blocks, none of the rows are verified before the end of the stream: they are dropped on error.
+    let (header, metadata, body_offset) = read_stream_start(data)?;
+    decode_frame_body_into(
+        options,
+        header,
+        &metadata,
+        data,
+        frame_start,
+        frame_start + body_offset,
+        out,
+    )
+}
+
+pub(crate) fn decode_frame_body_into(
+    options: &DecodingOptions,
+    header: Header,
+    metadata: &Metadata,
+    data: &mut impl Iterator<Item = u8>,
+    frame_start: u64,
+    body_start: u64,
+    out: &mut Vec<u8>,
+) -> io::Result<()> {
+    // Header row and body of a stream whose start is read, followed by its checksum and its row index footer
+    let out_start = out.len();
+    let position = Cell::new(body_start);
+    let data = &mut data.inspect(|_| position.set(position.get() + 1));
+    out.extend_from_slice(metadata.header_row.as_deref().unwrap_or_default());
+    let checksum = header.flags & FLAG_CHECKSUM != 0;
+    if header.flags & FLAG_BLOCKS != 0 {
+        decode_blocks_into(
+            data,
+            options.jobs,
+            body_start,
+            checksum,
+            |payload| decode_body(header, metadata, payload, 0),
+            out,
+        )?;
+    } else {
+        // The codes can read the 4 bytes of the checksum ahead, they are read again from the returned bytes
+        let start = out.len();
+        let decoded = if header.flags & FLAG_COLUMNAR != 0 {
+            decode_columnar(header, metadata, data).map(|rows| {
+                out.extend(rows);
+                Vec::new()
+            })
+        } else {
+            let lookahead = if checksum { CHECKSUM_LEN as u32 } else { 0 };
+            decode_stream_into(header, metadata, &mut *data, 0, lookahead, out)
+        };
+        let verified = decoded.and_then(|read_ahead| {
+            if !checksum {
+                return Ok(());
+            }
+            let body_end = position.get() - read_ahead.len() as u64;
+            let expected = read_checksum(&mut read_ahead.into_iter().chain(&mut *data))?;
+            verify_checksum(crc32(&out[start..]), expected, body_start..body_end)
+        });
+        if let Err(e) = verified {
+            if checksum {
+                out.truncate(out_start);
+            }
+            return Err(e);
+        }
+    }
+    if header.flags & FLAG_INDEX != 0 {
+        RowIndex::read(data, position.get() - frame_start)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn read_bytes<'a>(
+    reader: impl BufRead + 'a,
+    read_error: &'a mut Option<io::Error>,
+) -> impl Iterator<Item = u8> + 'a {
+    // Bytes of the reader up to its first error, which is kept to be reported by the caller
+    reader.bytes().map_while(move |b| match b {
+        Ok(b) => Some(b),
+        Err(e) => {
+            *read_error = Some(e);
+            None
+        }
+    })
+}
+
+fn decode_indexed_rows<'a, R: Read + Seek + 'a>(
+    header: Header,
+    metadata: &'a Metadata,
+    entry: IndexEntry,
+    mut reader: BufReader<R>,
+    read_error: &'a mut Option<io::Error>,
+) -> io::Result<impl Iterator<Item = io::Result<u8>> + 'a> {
+    // Rows of `decode_rows`, the reader being at the start of the block or body holding the index entry.
+    // The block offset of the entry is the position of the block in the input.
+    let decoded = if header.flags & FLAG_BLOCKS != 0 {
+        let mut first_block = true;
+        Either::Left(flatten_bytes(
+            BlockReader::new(
+                read_bytes(reader, read_error),
+                entry.block_offset,
+                header.flags & FLAG_CHECKSUM != 0,
+            )
+            .map(move |block| {
+                let bit_offset = if first_block { entry.bit_offset } else { 0 };
+                first_block = false;
+                block.and_then(|payload| decode_body(header, metadata, &payload, bit_offset))
+            }),
+        ))
+    } else if header.flags & FLAG_COLUMNAR != 0 {
+        let rows = decode_columnar(header, metadata, &mut read_bytes(&mut reader, read_error))?;
+        Either::Right(Either::Left(Either::Left(rows.into_iter().map(Ok))))
+    } else if header.codebook == Codebook::Range {
+        Either::Right(Either::Left(Either::Right(decode_stream(
+            header,
+            metadata,
+            read_bytes(reader, read_error),
+            0,
+        ))))
+    } else {
+        let codes = read_symbols(
+            header,
+            metadata,
+            &mut read_bytes(&mut reader, &mut *read_error),
+        )?;
+        // Whole bytes are skipped by seeking, the remaining bits by the decoder
+        reader.seek_relative((entry.bit_offset / 8) as i64)?;
+        Either::Right(Either::Right(decode_codes(
+            codes,
+            metadata,
+            BitReader::new(read_bytes(reader, read_error)).skip(entry.bit_offset % 8),
+        )))
+    };
+    Ok(decoded)
+}
+
+fn write_rows(
+    decoded: impl Iterator<Item = io::Result<u8>>,
+    row: &mut u64,
+    rows: &Range<u64>,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    // Writes the bytes of the given rows, `row` being the number of the row of the first decoded byte.
+    // Decoding stops at the end of the last row, a row ending in the next stream keeps its number.
+    let mut chunk = Vec::with_capacity(4096);
+    for b in decoded {
+        if *row >= rows.end {
+            break;
+        }
+        let b = b?;
+        if *row >= rows.start {
+            chunk.push(b);
+            if chunk.len() == chunk.capacity() {
+                writer.write_all(&chunk)?;
+                chunk.clear();
+            }
+        }
+        if b == b'\n' {
+            *row += 1;
+        }
+    }
+    writer.write_all(&chunk)
+}
+
+fn read_stream_header<R: Read + Seek>(
+    reader: &mut R,
+    start: u64,
+) -> io::Result<(Header, Metadata)> {
+    // Header and metadata of the stream starting at the given position of the input
+    let mut header = [0_u8; HEADER_LEN];
+    reader.seek(SeekFrom::Start(start))?;
+    reader.read_exact(&mut header)?;
+    let header = Header::read(&mut header.into_iter())?;
+    let metadata = if header.flags & FLAG_METADATA != 0 {
+        let mut section_len = [0_u8; 4];
+        reader.read_exact(&mut section_len)?;
+        // The section grows with the bytes read, a corrupted length doesn't allocate it up front
+        let mut section = Vec::new();
+        reader
+            .take(u64::from(u32::from_le_bytes(section_len)))
+            .read_to_end(&mut section)?;
+        Metadata::read(&mut section_len.into_iter().chain(section))?.0
+    } else {
+        Metadata::default()
+    };
+    Ok((header, metadata))
+}
+
+fn read_indexed_streams<R: Read + Seek>(
+    reader: &mut R,
+) -> io::Result<Vec<(u64, Header, Metadata, RowIndex)>> {
+    // Start, header, metadata and row index of every stream of the input, found from the last one.
+    // Each footer gives the start of its stream, which is the end of the previous stream.
+    let mut streams = Vec::new();
+    let mut end = reader.seek(SeekFrom::End(0))?;
+    loop {
+        let (index, start) = RowIndex::read_before(reader, end)?;
+        let (header, metadata) = read_stream_header(reader, start)?;
+        if header.flags & FLAG_INDEX == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Invalid row index : the stream at byte {} has a footer but no row index",
+                    start
+                ),
+            ));
+        }
+        streams.push((start, header, metadata, index));
+        if start == 0 {
+            streams.reverse();
+            return Ok(streams);
+        }
+        end = start;
+    }
+}
 
 pub struct Utf8Converter;
+impl Utf8Converter {
+    pub fn encode_with_options(
+        options: &EncodingOptions,
+        raw_data: impl IntoIterator<Item = u8>,
+    ) -> impl Iterator<Item = std::io::Result<u8>> {
+        let (header, metadata, raw_data) = match start_stream(options, raw_data.into_iter()) {
+            Ok(start) => start,
+            Err(e) => return Either::Right(iter::once(Err(e))),
+        };
+        let codebook = header.codebook;
+        let index_interval = options.index_interval;
+        let checksum = options.checksum;
+        let crlf = header.flags & FLAG_CRLF != 0;
+        let columnar = header.flags & FLAG_COLUMNAR != 0;
+        let map = metadata.encoding_map();
+        let start_bytes = stream_start_bytes(header, &metadata);
+        let body_offset = start_bytes.len() as u64;
+        let header_bytes = start_bytes.into_iter().map(Ok);
+        let encoded = match (codebook, options.block_size) {
+            (_, Some(block_size)) => {
+                Either::Left(header_bytes.chain(flatten_bytes(encode_blocks(
+                    raw_data,
+                    block_size,
+                    options.jobs,
+                    body_offset,
+                    index_interval,
+                    checksum,
+                    {
+                        let stats = options.stats.clone();
+                        let metadata = metadata.clone();
+                        move |block| encode_body(header, &metadata, block, index_interval, &stats)
+                    },
+                ))))
+            }
+            (Codebook::Utf8Static, None) if !columnar => {
+                // Rows are indexed and checksummed while the input goes through the lazy encoder,
+                // which also counts the bytes of the body
+                let (lookup_table, tokens, eoc_code) = static_codes(crlf);
+                let state = Rc::new(RefCell::new((
+                    RowIndexer::new(index_interval.unwrap_or(u64::MAX)),
+                    Crc32::new(),
+                    0_u64,
+                )));
+                let (checksummer, counter, body_len) =
+                    (state.clone(), state.clone(), state.clone());
+                let stats = options.stats.clone();
+                let raw_data = raw_data
+                    .into_iter()
+                    .inspect(move |&b| checksummer.borrow_mut().1.push(b))
+                    .map(move |b| map[usize::from(b)]);
+                let data = tokenize(raw_data, tokens).inspect(move |t| {
+                    count_token(
+                        &mut counter.borrow_mut().0,
+                        &stats,
+                        lookup_table,
+                        SYMB_ESC.code,
+                        t,
+                    )
+                });
+                let footer = iter::once_with(move || {
+                    let (indexer, crc, body_len) = &*state.borrow();
+                    let mut footer = Vec::new();
+                    if checksum {
+                        footer.extend(crc.value().to_le_bytes());
+                    }
+                    if let Some(interval) = index_interval {
+                        let footer_offset = body_offset + body_len + footer.len() as u64;
+                        footer.extend(index_footer(interval, body_offset, footer_offset, indexer));
+                    }
+                    footer
+                })
+                .flatten()
+                .map(Ok);
+                Either::Right(Either::Left(
+                    header_bytes
+                        .chain(
+                            encode(lookup_table, eoc_code, Some(SYMB_ESC.code), data)
+                                .inspect(move |_| body_len.borrow_mut().2 += 1),
+                        )
+                        .chain(footer),
+                ))
+            }
+            (_, None) => Either::Right(Either::Right(header_bytes.chain({
+                let raw_data = raw_data.collect::<Vec<u8>>();
+                match encode_rows(header, &metadata, &raw_data, options, body_offset) {
+                    Ok(body) => Either::Left(body.into_iter().map(Ok)),
+                    Err(e) => Either::Right(iter::once(Err(e))),
+                }
+            }))),
+        };
+        Either::Left(encoded)
+    }
+
+    pub fn encode_into_with_options(
+        options: &EncodingOptions,
+        raw_data: &[u8],
+        out: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        // Same stream as `encode_with_options`, encoded from a buffer already in memory.
+        // The rows are encoded in one go, nothing is appended to `out` when the options are rejected.
+        let (header, metadata, raw_data) = start_stream(options, raw_data.iter().copied())?;
+        let start_bytes = stream_start_bytes(header, &metadata);
+        let body_offset = start_bytes.len() as u64;
+        let raw_data = raw_data.collect::<Vec<u8>>();
+        out.extend(start_bytes);
+        match options.block_size {
+            Some(block_size) => {
+                let stats = &options.stats;
+                let records = encode_blocks(
+                    raw_data,
+                    block_size,
+                    options.jobs,
+                    body_offset,
+                    options.index_interval,
+                    options.checksum,
+                    |block| encode_body(header, &metadata, block, options.index_interval, stats),
+                );
+                for record in records {
+                    out.extend(record?);
+                }
+            }
+            None => out.extend(encode_rows(
+                header,
+                &metadata,
+                &raw_data,
+                options,
+                body_offset,
+            )?),
+        }
+        Ok(())
+    }
+
+    pub fn decode_with_options(
+        options: &DecodingOptions,
+        data: impl IntoIterator<Item = u8>,
+    ) -> impl Iterator<Item = std::io::Result<u8>> {
+        // Concatenated streams are decoded one after the other, as gzip does with its members.
+        // Each stream is read up to its last byte, the next one starts right after it.
+        let position = Rc::new(Cell::new(0_u64));
+        let counter = position.clone();
+        let data = Rc::new(RefCell::new(
+            data.into_iter()
+                .inspect(move |_| counter.set(counter.get() + 1))
+                .peekable(),
+        ));
+        let options = options.clone();
+        let mut first = true;
+        iter::from_fn(move || {
+            let peeked = data.borrow_mut().peek().is_some();
+            if !first && !peeked {
+                return None;
+            }
+            first = false;
+            // The peeked byte is the first one of the stream
+            let frame_start = position.get() - u64::from(peeked);
+            Some(decode_frame(
+                &options,
+                data.clone(),
+                frame_start,
+                position.clone(),
+            ))
+        })
+        .flatten()
+        .scan(false, |failed, b| {
+            // Nothing is decoded after an error, the next stream can't be found
+            if *failed {
+                return None;
+            }
+            *failed = b.is_err();
+            Some(b)
+        })
+    }
+
+    pub fn decode_into_with_options(
+        options: &DecodingOptions,
+        data: &[u8],
+        out: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        // Same csv as `decode_with_options`, appended to `out`.
+        // On error, `out` keeps what was decoded before it.
+        let len = data.len();
+        let mut data = data.iter().copied().peekable();
+        loop {
+            let frame_start = (len - data.len()) as u64;
+            decode_frame_into(options, &mut data, frame_start, out)?;
+            if data.peek().is_none() {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn appending_options<R: Read + Seek>(reader: &mut R) -> io::Result<EncodingOptions> {
+        // Options encoding new rows like the first stream of an existing file, without header row.
+        // The column encodings and the tokens are fitted again to the new rows, the row index
+        // interval is the one of the last stream.
+        let (header, metadata) = read_stream_header(reader, 0)?;
+        let index_interval = if header.flags & FLAG_INDEX != 0 {
+            let end = reader.seek(SeekFrom::End(0))?;
+            Some(RowIndex::read_before(reader, end)?.0.interval)
+        } else {
+            None
+        };
+        let fitted = |columns: bool| {
+            if columns {
+                ColumnSelection::Auto
+            } else {
+                ColumnSelection::None
+            }
+        };
+        Ok(EncodingOptions {
+            codebook: match header.codebook {
+                Codebook::Utf8Numeric => Codebook::Utf8Static,
+                codebook => codebook,
+            },
+            checksum: header.flags & FLAG_CHECKSUM != 0,
+            line_endings: if header.flags & FLAG_NORMALIZED_EOL != 0 {
+                LineEndings::Normalize
+            } else {
+                LineEndings::Preserve
+            },
+            columnar: header.flags & FLAG_COLUMNAR != 0,
+            delta: fitted(!metadata.delta_columns.is_empty()),
+            xor_floats: fitted(!metadata.xor_columns.is_empty()),
+            scaled: fitted(!metadata.scaled_columns.is_empty()),
+            tokens: !metadata.tokens.is_empty(),
+            header_row: HeaderRow::Absent,
+            delimiter: metadata.delimiter,
+            decimal_mark: metadata.decimal_mark,
+            block_size: metadata.block_size,
+            index_interval,
+            ..EncodingOptions::default()
+        })
+    }
+
+    pub fn append<F: Write + Seek>(
+        file: &mut F,
+        options: &EncodingOptions,
+        raw_data: &[u8],
+    ) -> io::Result<()> {
+        // Adds the rows as a new stream at the end of the file, decoded after the previous ones.
+        // The stream is encoded before anything is written, a failed encoding leaves the file untouched.
+        let mut encoded = Vec::new();
+        Utf8Converter::encode_into_with_options(options, raw_data, &mut encoded)?;
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&encoded)
+    }
+
+    pub fn read_metadata(data: impl IntoIterator<Item = u8>) -> io::Result<Metadata> {
+        // Reads the stream up to the end of its metadata section, see `Metadata::column_names`
+        read_stream_start(&mut data.into_iter()).map(|(_, metadata, _)| metadata)
+    }
+
+    pub fn decode_rows<R: Read + Seek>(
+        reader: &mut R,
+        rows: Range<u64>,
+        writer: &mut impl Write,
+    ) -> io::Result<()> {
+        // Decodes only the given rows, counted as in the csv of the whole input after its header row.
+        // With row indexes, the streams holding the rows are found from their footers, and each one
+        // is decoded from its closest entry. Without, the whole input is decoded.
+        let (header, metadata) = read_stream_header(reader, 0)?;
+        if let Some(header_row) = &metadata.header_row {
+            writer.write_all(header_row)?;
+        }
+        let mut row = 0;
+        if header.flags & FLAG_INDEX == 0 {
+            // A failed read ends the input, its error is reported instead of the truncated stream
+            let mut read_error = None;
+            // The header row of the first stream is already written
+            let header_len = metadata.header_row.as_ref().map_or(0, Vec::len);
+            reader.seek(SeekFrom::Start(0))?;
+            let decoded = Utf8Converter::decode_with_options(
+                &DecodingOptions::default(),
+                read_bytes(BufReader::new(reader), &mut read_error),
+            );
+            let written = write_rows(decoded.skip(header_len), &mut row, &rows, writer);
+            return match read_error {
+                Some(e) => Err(e),
+                None => written,
+            };
+        }
+        let streams = read_indexed_streams(reader)?;
+        for (k, (start, header, metadata, index)) in streams.iter().enumerate() {
+            if row >= rows.end {
+                break;
+            }
+            // Header rows of the following streams are rows of the decoded csv
+            let header_row = metadata
+                .header_row
+                .as_deref()
+                .filter(|_| k > 0)
+                .unwrap_or_default();
+            let header_lines = header_row.iter().filter(|&&b| b == b'\n').count() as u64;
+            let body_row = row + header_lines;
+            if rows.start > body_row + index.rows && k + 1 < streams.len() {
+                row = body_row + index.rows;
+                continue;
+            }
+            // An empty body has no entry, only the header row is left to write
+            if index.entries.is_empty() && index.rows == 0 {
+                write_rows(header_row.iter().copied().map(Ok), &mut row, &rows, writer)?;
+                row = body_row;
+                continue;
+            }
+            let entry = if rows.start > body_row {
+                index.lookup(rows.start - body_row)
+            } else {
+                write_rows(header_row.iter().copied().map(Ok), &mut row, &rows, writer)?;
+                index.lookup(0)
+            }
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Row index has no entry"))?;
+            row = body_row + entry.row;
+            let block_offset = start.checked_add(entry.block_offset).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid row index : block offset past the end of the input",
+                )
+            })?;
+            reader.seek(SeekFrom::Start(block_offset))?;
+            let mut read_error = None;
+            let written = decode_indexed_rows(
+                *header,
+                metadata,
+                IndexEntry {
+                    block_offset,
+                    ..*entry
+                },
+                BufReader::new(&mut *reader),
+                &mut read_error,
+            )
+            .and_then(|decoded| write_rows(decoded, &mut row, &rows, writer));
+            if let Some(e) = read_error {
+                return Err(e);
+            }
+            written?;
+            // The rows of the stream are all decoded, even when the csv doesn't end with a newline
+            row = body_row + index.rows;
+        }
+        Ok(())
+    }
+}
 impl BnCsvConverter for Utf8Converter {
     fn encode(raw_data: impl IntoIterator<Item = u8>) -> impl Iterator<Item = std::io::Result<u8>> {
-        encode(&UTF_8_LOOKUP_ENCODING_TABLE, &SYMB_EOC, raw_data)
+        Utf8Converter::encode_with_options(&EncodingOptions::default(), raw_data)
     }
     fn decode(data: impl IntoIterator<Item = u8>) -> impl Iterator<Item = std::io::Result<u8>> {
-        decode(
-            DecodingTree::new(&[
-                SYMB_0,
-                SYMB_1,
-                SYMB_2,
-                SYMB_3,
-                SYMB_4,
-                SYMB_5,
-                SYMB_6,
-                SYMB_7,
-                SYMB_8,
-                SYMB_9,
-                SYMB_COMMA,
-                SYMB_DOT,
-                SYMB_MINUS,
-                SYMB_NEWLINE,
-                SYMB_EOC,
-            ]),
-            &SYMB_EOC,
-            data.into_iter()
-                .map(|b| Ok::<Vec<u8>, io::Error>((0..=7).rev().map(|i| b >> i & 1_u8).collect()))
-                .flat_map(|x| x.expect("Failed to read bytes")),
-        )
+        Utf8Converter::decode_with_options(&DecodingOptions::default(), data)
+    }
+    fn encode_into(raw_data: &[u8], out: &mut Vec<u8>) -> std::io::Result<()> {
+        Utf8Converter::encode_into_with_options(&EncodingOptions::default(), raw_data, out)
+    }
+    fn decode_into(data: &[u8], out: &mut Vec<u8>) -> std::io::Result<()> {
+        Utf8Converter::decode_into_with_options(&DecodingOptions::default(), data, out)
     }
 }
@@ -0,0 +1,173 @@
+use std::io;
+
+use crate::fmt::columnar::{column_values, value_section, ValueSection};
+use crate::utils::bits::{BitReader, BitWriter};
+
+// XOR encoding of the float columns of columnar streams, as in Facebook's Gorilla : the bits of each double
+// are XORed with the previous one and only the bits between the leading and trailing zeros are kept.
+// Fields are stored as doubles when formatting the double gives back the same text, the other fields
+// stay in the text column encoded with the codebook. The value section of a column holds bits, starting
+// with the form of its doubles (0 : as printed by `{}`, 1 : as printed by `{:e}`), followed by one of these
+// per field :
+// 0 : next value of the text column
+// 10 : same double as the previous one
+// 110 : meaningful bits, within the leading and trailing zeros of the previous XOR
+// 111 : leading zeros (5 bits) | meaningful bits count minus 1 (6 bits) | meaningful bits
+const LEADING_ZEROS_BITS: u8 = 5;
+const MEANINGFUL_BITS: u8 = 6;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum FloatForm {
+    // Shortest form without exponent, such as `0.0000001`
+    Plain,
+    // Shortest form with an exponent, such as `1e-7`
+    Exponent,
+}
+
+impl FloatForm {
+    fn format(self, float: f64) -> String {
+        match self {
+            FloatForm::Plain => format!("{}", float),
+            FloatForm::Exponent => format!("{:e}", float),
+        }
+    }
+
+    fn of_column(column: &[u8]) -> Self {
+        // Form giving back the most values of the column
+        let count = |form| {
+            column_values(column)
+                .filter(|value| parse_float(value, form).is_some())
+                .count()
+        };
+        if count(FloatForm::Exponent) > count(FloatForm::Plain) {
+            FloatForm::Exponent
+        } else {
+            FloatForm::Plain
+        }
+    }
+}
+
+fn invalid_floats(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid float column : {}", msg),
+    )
+}
+
+fn parse_float(value: &[u8], form: FloatForm) -> Option<f64> {
+    // Double of the value, when it is written in the shortest form of the given kind giving it back
+    let float = std::str::from_utf8(value).ok()?.parse::<f64>().ok()?;
+    (form.format(float).as_bytes() == value).then_some(float)
+}
+
+fn write_bits(bits: &mut BitWriter, value: u64, n_bits: u8) {
+    // The writer takes at most 32 bits at a time
+    let high = n_bits.saturating_sub(32);
+    bits.write((value >> 32) as u32, high);
+    bits.write(value as u32, n_bits - high);
+}
+
+fn read_bits(bits: &mut BitReader<impl Iterator<Item = u8>>, n_bits: u8) -> io::Result<u64> {
+    // The reader gives at most 32 bits at a time
+    let high = n_bits.saturating_sub(32);
+    let mut read = |len| {
+        bits.read(len)
+            .map(u64::from)
+            .ok_or_else(|| invalid_floats("truncated bits"))
+    };
+    let high_bits = read(high)?;
+    Ok(high_bits << (n_bits - high) | read(n_bits - high)?)
+}
+
+pub fn xor_encode(column: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    // Value section and text column of the fields that aren't stored as doubles
+    let form = FloatForm::of_column(column);
+    let mut bits = BitWriter::default();
+    bits.write(u32::from(form == FloatForm::Exponent), 1);
+    let mut text = Vec::new();
+    let mut n_values = 0_u64;
+    let mut previous = 0_u64;
+    // Leading and trailing zeros of the last XOR written with its own window
+    let mut window: Option<(u32, u32)> = None;
+    for value in column_values(column) {
+        n_values += 1;
+        let Some(float) = parse_float(value, form) else {
+            bits.write(0b0, 1);
+            text.extend(value);
+            text.push(b',');
+            continue;
+        };
+        let xor = float.to_bits() ^ previous;
+        previous = float.to_bits();
+        if xor == 0 {
+            bits.write(0b10, 2);
+            continue;
+        }
+        let leading = xor.leading_zeros().min((1 << LEADING_ZEROS_BITS) - 1);
+        let trailing = xor.trailing_zeros();
+        match window {
+            Some((w_leading, w_trailing)) if leading >= w_leading && trailing >= w_trailing => {
+                bits.write(0b110, 3);
+                write_bits(
+                    &mut bits,
+                    xor >> w_trailing,
+                    (64 - w_leading - w_trailing) as u8,
+                );
+            }
+            _ => {
+                let meaningful = 64 - leading - trailing;
+                bits.write(0b111, 3);
+                bits.write(leading, LEADING_ZEROS_BITS);
+                bits.write(meaningful - 1, MEANINGFUL_BITS);
+                write_bits(&mut bits, xor >> trailing, meaningful as u8);
+                window = Some((leading, trailing));
+            }
+        }
+    }
+    (value_section(n_values, &bits.finish()), text)
+}
+
+pub fn xor_decode(section: ValueSection, text: &[u8]) -> io::Result<Vec<u8>> {
+    // Column rebuilt from its value section and its decoded text column
+    let (n_values, packed) = section;
+    let mut bits = BitReader::new(packed.iter().copied());
+    let form = match read_bits(&mut bits, 1)? {
+        0 => FloatForm::Plain,
+        _ => FloatForm::Exponent,
+    };
+    let mut text = column_values(text);
+    let mut out = Vec::new();
+    let mut previous = 0_u64;
+    let mut window: Option<(u32, u32)> = None;
+    for _ in 0..n_values {
+        if read_bits(&mut bits, 1)? == 0 {
+            out.extend(
+                text.next()
+                    .ok_or_else(|| invalid_floats("missing text value"))?,
+            );
+            out.push(b',');
+            continue;
+        }
+        if read_bits(&mut bits, 1)? == 1 {
+            let (leading, trailing) = if read_bits(&mut bits, 1)? == 0 {
+                window.ok_or_else(|| invalid_floats("missing leading and trailing zeros"))?
+            } else {
+                let leading = read_bits(&mut bits, LEADING_ZEROS_BITS)? as u32;
+                let meaningful = read_bits(&mut bits, MEANINGFUL_BITS)? as u32 + 1;
+                let trailing = 64_u32
+                    .checked_sub(leading + meaningful)
+                    .ok_or_else(|| invalid_floats("too many meaningful bits"))?;
+                window = Some((leading, trailing));
+                (leading, trailing)
+            };
+            let meaningful = read_bits(&mut bits, (64 - leading - trailing) as u8)?;
+            previous ^= meaningful << trailing;
+        }
+        out.extend(form.format(f64::from_bits(previous)).as_bytes());
+        out.push(b',');
+    }
+    if text.next().is_some() {
+        return Err(invalid_floats("unused text values"));
+    }
+    Ok(out)
+}
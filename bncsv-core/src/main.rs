@@ -1,7 +1,3 @@
-mod compr;
-mod fmt;
-mod utils;
-
 use std::io;
 #[cfg(not(feature = "cli"))]
 fn main() {
@@ -0,0 +1,151 @@
+// Packed bit writer and reader, the first bit of a stream being the most significant bit of its first byte.
+// Codes are given as right-aligned values along with their number of bits, at most 32.
+
+#[derive(Default, Debug)]
+pub struct BitWriter {
+    // Bits not yet written to a byte, right-aligned
+    acc: u64,
+    n_bits: u32,
+    bytes: Vec<u8>,
+}
+
+impl BitWriter {
+    pub fn write(&mut self, value: u32, len: u8) {
+        // Bits are moved to the bytes 32 at a time, less than 32 bits are kept in the accumulator
+        self.acc = self.acc << len | u64::from(value);
+        self.n_bits += u32::from(len);
+        if self.n_bits >= 32 {
+            self.n_bits -= 32;
+            self.bytes
+                .extend_from_slice(&((self.acc >> self.n_bits) as u32).to_be_bytes());
+        }
+    }
+
+    fn flush_bytes(&mut self) {
+        while self.n_bits >= 8 {
+            self.n_bits -= 8;
+            self.bytes.push((self.acc >> self.n_bits) as u8);
+        }
+    }
+
+    pub fn take_bytes(&mut self) -> Vec<u8> {
+        // Whole bytes written so far, the bits of an incomplete byte are kept
+        self.flush_bytes();
+        std::mem::take(&mut self.bytes)
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        // Completes the last byte with zeros
+        self.flush_bytes();
+        if self.n_bits > 0 {
+            self.write(0, (8 - self.n_bits) as u8);
+            self.flush_bytes();
+        }
+        self.bytes
+    }
+}
+
+pub struct BitReader<I: Iterator<Item = u8>> {
+    // Bytes are only read when their bits are needed, so that the input stops right after the last byte read.
+    // Up to `lookahead` more bytes can be read ahead, when the input is known to hold them.
+    iter: I,
+    acc: u64,
+    n_bits: u32,
+    lookahead: u32,
+}
+
+impl<I: Iterator<Item = u8>> BitReader<I> {
+    pub fn new(iter: I) -> Self {
+        BitReader::with_lookahead(iter, 0)
+    }
+
+    pub fn with_lookahead(iter: I, bytes: u32) -> Self {
+        // At most 7 bytes, the accumulator holding them along with the byte being consumed
+        BitReader {
+            iter,
+            acc: 0,
+            n_bits: 0,
+            lookahead: bytes.min(7),
+        }
+    }
+
+    fn fill(&mut self, len: u32) -> bool {
+        while self.n_bits < len {
+            match self.iter.next() {
+                Some(b) => {
+                    self.acc = self.acc << 8 | u64::from(b);
+                    self.n_bits += 8;
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+
+    pub fn read(&mut self, len: u8) -> Option<u32> {
+        // Next `len` bits (at most 32), none when the input ends before them
+        if !self.fill(u32::from(len)) {
+            return None;
+        }
+        let bits = self.peek(len);
+        self.consume(len);
+        Some(bits)
+    }
+
+    pub fn read_ahead(&mut self) {
+        // Reads the bytes following the one of the next bit that the lookahead allows, fewer at the end of the input
+        while self.n_bits < 8 * self.lookahead {
+            match self.iter.next() {
+                Some(b) => {
+                    self.acc = self.acc << 8 | u64::from(b);
+                    self.n_bits += 8;
+                }
+                None => return,
+            }
+        }
+    }
+
+    pub fn into_lookahead(self) -> Vec<u8> {
+        // Whole bytes read and not consumed, the bits left of the byte of the last consumed bit are dropped
+        (0..self.n_bits / 8)
+            .rev()
+            .map(|i| (self.acc >> (8 * i)) as u8)
+            .collect()
+    }
+
+    pub fn read_byte(&mut self) -> bool {
+        // Reads one more byte of the input, false at its end
+        self.fill(self.n_bits + 8)
+    }
+
+    pub fn available(&self) -> u32 {
+        // Bits read from the input and not consumed yet
+        self.n_bits
+    }
+
+    pub fn peek(&self, len: u8) -> u32 {
+        // Next `len` bits among the bytes read so far, the missing bits being zeros.
+        // The bits are moved to the top of the accumulator in two shifts, an empty one needing 64.
+        let bits = self.acc << (63 - self.n_bits) << 1;
+        (bits >> 1 >> (63 - u32::from(len))) as u32
+    }
+
+    pub fn consume(&mut self, len: u8) {
+        // Drops the next `len` bits, which must have been read already
+        self.n_bits -= u32::from(len);
+    }
+
+    pub fn skip(mut self, len: u64) -> Self {
+        // Skips `len` bits, whole bytes being skipped without looking at them.
+        // Past the end of the input, the reads that follow return none.
+        match len.checked_sub(u64::from(self.n_bits)) {
+            None => self.n_bits -= len as u32,
+            Some(rest) => {
+                self.n_bits = 0;
+                self.iter.by_ref().take((rest / 8) as usize).for_each(drop);
+                self.read((rest % 8) as u8);
+            }
+        }
+        self
+    }
+}
@@ -2,10 +2,16 @@
 mod tests {
     use assert_cmd::Command;
     use assert_fs::TempDir;
+    use bncsv_core::compr::BnCsvConverter;
+    use bncsv_core::fmt::columnar::ColumnSelection;
+    use bncsv_core::fmt::header::Codebook;
+    use bncsv_core::fmt::stream::{BncsvDecoder, BncsvEncoder};
+    use bncsv_core::fmt::utf8::{DecodingOptions, EncodingOptions, Utf8Converter};
     use predicates::prelude::*;
     use std::ffi::OsStr;
     use std::fs::{self, File};
-    use std::io::Write;
+    use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+    use std::ops::Range;
     use std::path::PathBuf;
 
     use rand::Rng;
@@ -61,6 +67,34 @@ mod tests {
         bncsv_cmd
     }
 
+    // Reader whose reads starting in `failing` return an error, as a dropped connection would
+    struct FailingReader {
+        inner: Cursor<Vec<u8>>,
+        failing: Range<u64>,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let position = self.inner.position();
+            if self.failing.contains(&position) {
+                return Err(io::Error::from(io::ErrorKind::ConnectionReset));
+            }
+            let available = self.failing.start.saturating_sub(position) as usize;
+            let len = if position < self.failing.start {
+                buf.len().min(available)
+            } else {
+                buf.len()
+            };
+            self.inner.read(&mut buf[..len])
+        }
+    }
+
+    impl Seek for FailingReader {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
     fn assert_file(path: &PathBuf) {
         assert!(path.exists(), "File {} does not exist", path.display());
         assert!(path.is_file(), "File {} is not a file", path.display());
@@ -79,6 +113,1606 @@ mod tests {
             .success()
             .stdout(predicate::str::contains("Usage: bncsv"));
     }
+    #[test]
+    fn test_decode_rejects_foreign_file() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("foreign.csv"));
+        run_cli_command(
+            &vec![csv_path.to_str().unwrap().to_string()],
+            "bncsv",
+            None,
+            None,
+        )
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("magic bytes"));
+    }
+    #[test]
+    fn test_encode_decode_adaptive_codebook() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let bncsv_path = root.join("data.bncsv");
+        let decoded_path = root.join("decoded.csv");
+        run_cli_command(
+            &vec![csv_path.to_str().unwrap().to_string()],
+            "csv",
+            Some(&bncsv_path),
+            None,
+        )
+        .args(["-c", "adaptive"])
+        .assert()
+        .success();
+        run_cli_command(
+            &vec![bncsv_path.to_str().unwrap().to_string()],
+            "bncsv",
+            Some(&decoded_path),
+            None,
+        )
+        .assert()
+        .success();
+        assert_eq!(fs::read(&bncsv_path).unwrap()[5], 2);
+        assert_eq!(fs::read(csv_path).unwrap(), fs::read(decoded_path).unwrap());
+    }
+    #[test]
+    fn test_encode_decode_range_codebook() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let csv = fs::read(&csv_path).unwrap();
+        let csv_arg = vec![csv_path.to_str().unwrap().to_string()];
+        let bncsv_path = root.join("encoded.bncsv");
+        let mut encoded_lens = Vec::new();
+        for extra_args in [
+            vec!["-c", "adaptive"],
+            vec!["-c", "range"],
+            vec!["-c", "range", "-b", "4096", "-j", "4"],
+            vec!["-c", "range", "--columnar"],
+        ] {
+            let encoded = run_cli_command(&csv_arg, "csv", None, None)
+                .args(&extra_args)
+                .args(["--index-interval", "64"])
+                .assert()
+                .success();
+            fs::write(&bncsv_path, &encoded.get_output().stdout).unwrap();
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .assert()
+            .success()
+            .stdout(csv.clone());
+            // Rows are found back from the start of the body or of their block
+            let expected = String::from_utf8(csv.clone()).unwrap();
+            let expected = expected
+                .lines()
+                .skip(500)
+                .take(10)
+                .map(|l| format!("{}\n", l));
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .args(["--rows", "500..510"])
+            .assert()
+            .success()
+            .stdout(expected.collect::<String>());
+            encoded_lens.push(encoded.get_output().stdout.len());
+        }
+        assert_eq!(fs::read(&bncsv_path).unwrap()[5], 5);
+        assert!(encoded_lens[1] < encoded_lens[0]);
+    }
+    #[test]
+    fn test_encode_decode_context_codebook() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let csv = fs::read(&csv_path).unwrap();
+        let csv_arg = vec![csv_path.to_str().unwrap().to_string()];
+        let bncsv_path = root.join("encoded.bncsv");
+        let expected = String::from_utf8(csv.clone()).unwrap();
+        let expected = expected
+            .lines()
+            .skip(500)
+            .take(10)
+            .map(|l| format!("{}\n", l))
+            .collect::<String>();
+        let mut encoded_lens = Vec::new();
+        for extra_args in [
+            vec!["-c", "adaptive"],
+            vec!["-c", "context"],
+            vec!["-c", "context", "-b", "4096", "-j", "4"],
+        ] {
+            let encoded = run_cli_command(&csv_arg, "csv", None, None)
+                .args(&extra_args)
+                .args(["--index-interval", "64"])
+                .assert()
+                .success();
+            fs::write(&bncsv_path, &encoded.get_output().stdout).unwrap();
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .assert()
+            .success()
+            .stdout(csv.clone());
+            // Decoding from an index entry starts in the newline context
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .args(["--rows", "500..510"])
+            .assert()
+            .success()
+            .stdout(expected.clone());
+            encoded_lens.push(encoded.get_output().stdout.len());
+        }
+        assert_eq!(fs::read(&bncsv_path).unwrap()[5], 6);
+        assert!(encoded_lens[1] < encoded_lens[0]);
+    }
+    #[test]
+    fn test_encode_decode_tokens() {
+        let root = TempDir::new().unwrap();
+        let csv_path = root.join("data.csv");
+        // Nearly every field starts with `0.`
+        let mut rng = rand::thread_rng();
+        let csv = (0..1024)
+            .map(|_| {
+                let row = (0..6)
+                    .map(|_| format!("{:.6}", rng.gen_range(-1.0..1.0)))
+                    .collect::<Vec<String>>();
+                format!("{}\n", row.join(","))
+            })
+            .collect::<String>();
+        fs::write(&csv_path, &csv).unwrap();
+        let csv_arg = vec![csv_path.to_str().unwrap().to_string()];
+        let bncsv_path = root.join("encoded.bncsv");
+        let expected = csv
+            .lines()
+            .skip(300)
+            .take(5)
+            .map(|l| format!("{}\n", l))
+            .collect::<String>();
+        for codebook in ["adaptive", "context", "range", "columns"] {
+            let mut encoded_lens = Vec::new();
+            for extra_args in [vec![], vec!["--tokens"], vec!["--tokens", "-b", "4096"]] {
+                let encoded = run_cli_command(&csv_arg, "csv", None, None)
+                    .args(["-c", codebook, "--index-interval", "64"])
+                    .args(&extra_args)
+                    .assert()
+                    .success();
+                fs::write(&bncsv_path, &encoded.get_output().stdout).unwrap();
+                let bncsv_arg = vec![bncsv_path.to_str().unwrap().to_string()];
+                run_cli_command(&bncsv_arg, "bncsv", None, None)
+                    .assert()
+                    .success()
+                    .stdout(csv.clone());
+                run_cli_command(&bncsv_arg, "bncsv", None, None)
+                    .args(["--rows", "300..305"])
+                    .assert()
+                    .success()
+                    .stdout(expected.clone());
+                encoded_lens.push(encoded.get_output().stdout.len());
+            }
+            assert!(encoded_lens[1] < encoded_lens[0], "{}", codebook);
+        }
+        // The hard-coded codebook has no code for the learned tokens
+        run_cli_command(&csv_arg, "csv", None, None)
+            .args(["-c", "static", "--tokens"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("need a codebook fitted"));
+    }
+    #[test]
+    fn test_encode_decode_blocks() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        for codebook in ["static", "adaptive"] {
+            let bncsv_path = root.join(format!("{}.bncsv", codebook));
+            let decoded_path = root.join(format!("{}.csv", codebook));
+            run_cli_command(
+                &vec![csv_path.to_str().unwrap().to_string()],
+                "csv",
+                Some(&bncsv_path),
+                None,
+            )
+            .args(["-c", codebook, "-b", "4096", "-j", "4"])
+            .assert()
+            .success();
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                Some(&decoded_path),
+                None,
+            )
+            .args(["-j", "3"])
+            .assert()
+            .success();
+            assert_eq!(fs::read(&csv_path).unwrap(), fs::read(decoded_path).unwrap());
+        }
+    }
+    #[test]
+    fn test_encode_decode_large_input() {
+        // Inputs larger than 16 MiB are converted as they are read, here from stdin when encoding
+        // and from a file holding the encoded stream twice when decoding
+        let root = TempDir::new().unwrap();
+        let mut rng = rand::thread_rng();
+        let mut csv = b"id,value,count\n".to_vec();
+        for i in 0.. {
+            if csv.len() > 17 << 20 {
+                break;
+            }
+            let row = format!(
+                "{},{:.3},{}\n",
+                i,
+                rng.gen_range(-1000.0..1000.0),
+                rng.gen_range(0..100000)
+            );
+            csv.extend(row.bytes());
+        }
+        let encoded = bncsv()
+            .args(["-p", "-i", "csv", "-b", "1048576"])
+            .write_stdin(csv.clone())
+            .assert()
+            .success();
+        let bncsv_path = root.join("large.bncsv");
+        fs::write(&bncsv_path, encoded.get_output().stdout.repeat(2)).unwrap();
+        assert!(fs::metadata(&bncsv_path).unwrap().len() > 16 << 20);
+        run_cli_command(
+            &vec![bncsv_path.to_str().unwrap().to_string()],
+            "bncsv",
+            None,
+            None,
+        )
+        .args(["-j", "4"])
+        .assert()
+        .success()
+        .stdout(csv.repeat(2));
+    }
+    #[test]
+    fn test_encode_decode_long_codes() {
+        let root = TempDir::new().unwrap();
+        let csv_path = root.join("skewed.csv");
+        // Symbol counts halving from one symbol to the next give codes longer than a decoding table lookup
+        let mut csv = String::new();
+        for (i, symbol) in "1234567890.-e+".chars().enumerate() {
+            csv.extend((0..1 << (13 - i)).map(|_| symbol));
+            csv.push_str(",NaN\n");
+        }
+        fs::write(&csv_path, &csv).unwrap();
+        for codebook in ["adaptive", "context"] {
+            let bncsv_path = root.join(format!("skewed_{}.bncsv", codebook));
+            let encoded = run_cli_command(
+                &vec![csv_path.to_str().unwrap().to_string()],
+                "csv",
+                None,
+                None,
+            )
+            .args(["-c", codebook])
+            .assert()
+            .success();
+            fs::write(&bncsv_path, &encoded.get_output().stdout).unwrap();
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .assert()
+            .success()
+            .stdout(csv.clone());
+        }
+    }
+    #[test]
+    fn test_decode_concatenated_streams() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let header_csv_path = root.join("header.csv");
+        fs::write(&header_csv_path, "time,temp\n1,20.5\n2,21.0\n").unwrap();
+        // Each stream keeps its own options and header row
+        let mut concatenated = Vec::new();
+        let mut expected = Vec::new();
+        for (path, extra_args) in [
+            (&csv_path, vec![]),
+            (&header_csv_path, vec![]),
+            (
+                &csv_path,
+                vec!["-c", "adaptive", "-b", "4096", "--index-interval", "64"],
+            ),
+            (
+                &csv_path,
+                vec!["-c", "context", "--no-checksum", "--index-interval", "64"],
+            ),
+            (&csv_path, vec!["-c", "columns", "--delta", "auto"]),
+            (&header_csv_path, vec!["-c", "range"]),
+        ] {
+            let encoded =
+                run_cli_command(&vec![path.to_str().unwrap().to_string()], "csv", None, None)
+                    .args(&extra_args)
+                    .assert()
+                    .success();
+            concatenated.extend(&encoded.get_output().stdout);
+            expected.extend(fs::read(path).unwrap());
+        }
+        let bncsv_path = root.join("concatenated.bncsv");
+        fs::write(&bncsv_path, &concatenated).unwrap();
+        run_cli_command(
+            &vec![bncsv_path.to_str().unwrap().to_string()],
+            "bncsv",
+            None,
+            None,
+        )
+        .assert()
+        .success()
+        .stdout(expected);
+        // Trailing bytes that don't start a stream are reported
+        concatenated.extend(b"junk");
+        fs::write(&bncsv_path, &concatenated).unwrap();
+        run_cli_command(
+            &vec![bncsv_path.to_str().unwrap().to_string()],
+            "bncsv",
+            None,
+            None,
+        )
+        .assert()
+        .failure();
+    }
+    #[test]
+    fn test_buffer_functions_match_iterators() {
+        // The buffer functions give the bytes of the iterators, for single and concatenated streams
+        let root = TempDir::new().unwrap();
+        let csv = fs::read(create_random_csv(&root.join("data.csv"))).unwrap();
+        let options = [
+            EncodingOptions::default(),
+            EncodingOptions {
+                codebook: Codebook::Adaptive,
+                block_size: Some(4096),
+                index_interval: Some(64),
+                ..Default::default()
+            },
+            EncodingOptions {
+                codebook: Codebook::Context,
+                checksum: false,
+                ..Default::default()
+            },
+            EncodingOptions {
+                codebook: Codebook::Range,
+                tokens: true,
+                ..Default::default()
+            },
+            EncodingOptions {
+                codebook: Codebook::PerColumn,
+                columnar: true,
+                delta: ColumnSelection::Auto,
+                ..Default::default()
+            },
+            EncodingOptions {
+                xor_floats: ColumnSelection::Auto,
+                ..Default::default()
+            },
+        ];
+        let mut concatenated = Vec::new();
+        for options in &options {
+            let encoded = Utf8Converter::encode_with_options(options, csv.iter().copied())
+                .collect::<io::Result<Vec<u8>>>()
+                .unwrap();
+            let mut encoded_into = Vec::new();
+            Utf8Converter::encode_into_with_options(options, &csv, &mut encoded_into).unwrap();
+            assert_eq!(encoded, encoded_into, "{:?}", options);
+            let decoded = Utf8Converter::decode(encoded.iter().copied())
+                .collect::<io::Result<Vec<u8>>>()
+                .unwrap();
+            assert_eq!(decoded, csv, "{:?}", options);
+            assert_eq!(
+                Utf8Converter::decode_slice(&encoded).unwrap(),
+                csv,
+                "{:?}",
+                options
+            );
+            concatenated.extend(encoded);
+        }
+        let encoded = Utf8Converter::encode(csv.iter().copied())
+            .collect::<io::Result<Vec<u8>>>()
+            .unwrap();
+        assert_eq!(Utf8Converter::encode_slice(&csv).unwrap(), encoded);
+        let mut encoded_into = b"kept".to_vec();
+        Utf8Converter::encode_into(&csv, &mut encoded_into).unwrap();
+        assert_eq!(encoded_into, [&b"kept"[..], &encoded].concat());
+
+        let decoded = Utf8Converter::decode(concatenated.iter().copied())
+            .collect::<io::Result<Vec<u8>>>()
+            .unwrap();
+        assert_eq!(decoded, csv.repeat(options.len()));
+        let mut decoded_into = b"kept".to_vec();
+        Utf8Converter::decode_into(&concatenated, &mut decoded_into).unwrap();
+        assert_eq!(decoded_into, [&b"kept"[..], &decoded].concat());
+    }
+    #[test]
+    fn test_buffer_functions_keep_decoded_prefix() {
+        // On error, the output keeps the streams and the verified blocks decoded before it
+        let root = TempDir::new().unwrap();
+        let csv = fs::read(create_random_csv(&root.join("data.csv"))).unwrap();
+        let first = Utf8Converter::encode_slice(&csv).unwrap();
+        let blocks = EncodingOptions {
+            block_size: Some(4096),
+            ..Default::default()
+        };
+        let mut second = Vec::new();
+        Utf8Converter::encode_into_with_options(&blocks, &csv, &mut second).unwrap();
+        let corrupted = first.len() + second.len() * 3 / 4;
+        let mut data = [first, second].concat();
+        data[corrupted] ^= 0x5a;
+
+        let mut decoded_into = Vec::new();
+        assert!(Utf8Converter::decode_into(&data, &mut decoded_into).is_err());
+        let decoded = Utf8Converter::decode(data.iter().copied())
+            .map_while(Result::ok)
+            .collect::<Vec<u8>>();
+        assert!(decoded_into.len() > csv.len());
+        assert!(decoded_into.len() < 2 * csv.len());
+        assert_eq!(decoded_into, decoded);
+        assert_eq!(decoded_into, csv.repeat(2)[..decoded_into.len()]);
+        assert_eq!(decoded_into.last(), Some(&b'\n'));
+        assert!(Utf8Converter::decode_slice(&data).is_err());
+    }
+    #[test]
+    fn test_append_rows() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let rows_path = root.join("rows.csv");
+        fs::write(&rows_path, "1.5,-2,3\n4,5.25,6\n").unwrap();
+        let csv = fs::read(&csv_path).unwrap();
+        for extra_args in [
+            vec![],
+            vec!["-c", "context", "-b", "4096"],
+            vec!["-c", "columns", "--delta", "auto"],
+        ] {
+            let bncsv_path = root.join("log.bncsv");
+            let _ = fs::remove_file(&bncsv_path);
+            // The first call creates the file with the given options, the next ones reuse them
+            for path in [&csv_path, &rows_path] {
+                bncsv()
+                    .args(&extra_args)
+                    .arg("append")
+                    .arg(&bncsv_path)
+                    .arg(path)
+                    .assert()
+                    .success();
+            }
+            bncsv()
+                .args(["-p", "append"])
+                .arg(&bncsv_path)
+                .write_stdin(fs::read(&rows_path).unwrap())
+                .assert()
+                .success();
+            let mut expected = csv.clone();
+            expected.extend(b"1.5,-2,3\n4,5.25,6\n1.5,-2,3\n4,5.25,6\n");
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .assert()
+            .success()
+            .stdout(expected);
+        }
+        // A corrupted metadata length is reported as truncated, without allocating it first
+        let bncsv_path = root.join("corrupted.bncsv");
+        let mut data = Utf8Converter::encode_slice(b"a,b\n1,2\n").unwrap();
+        data.truncate(11);
+        data[7..11].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&bncsv_path, data).unwrap();
+        bncsv()
+            .arg("append")
+            .arg(&bncsv_path)
+            .arg(&rows_path)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("Truncated bncsv metadata"));
+    }
+    #[test]
+    fn test_append_rows_indexed() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let rows_path = root.join("rows.csv");
+        fs::write(&rows_path, "1.5,-2,3\n4,5.25,6\n").unwrap();
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        let bncsv_path = root.join("log.bncsv");
+        bncsv()
+            .args(["-b", "2048", "--index-interval", "64", "append"])
+            .args([&bncsv_path, &csv_path])
+            .assert()
+            .success();
+        // The appended streams keep the blocks and the row index of the file
+        for _ in 0..2 {
+            bncsv()
+                .arg("append")
+                .args([&bncsv_path, &rows_path])
+                .assert()
+                .success();
+        }
+        bncsv()
+            .arg("append")
+            .arg(&bncsv_path)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("requires a single input file"));
+        // Options of the decoding or of the input paths don't apply to the appended rows
+        bncsv()
+            .args(["--rows", "0..10", "append"])
+            .args([&bncsv_path, &rows_path])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("only takes the encoding options"));
+        let options =
+            Utf8Converter::appending_options(&mut File::open(&bncsv_path).unwrap()).unwrap();
+        assert_eq!(options.block_size, Some(2048));
+        assert_eq!(options.index_interval, Some(64));
+        let expected = csv
+            .lines()
+            .skip(1020)
+            .map(|l| format!("{}\n", l))
+            .collect::<String>()
+            + "1.5,-2,3\n4,5.25,6\n1.5,-2,3\n";
+        run_cli_command(
+            &vec![bncsv_path.to_str().unwrap().to_string()],
+            "bncsv",
+            None,
+            None,
+        )
+        .args(["--rows", "1020..1027"])
+        .assert()
+        .success()
+        .stdout(expected);
+    }
+    #[test]
+    fn test_encoder_decoder_adapters() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let csv = fs::read(&csv_path).unwrap();
+        for (name, extra_args, options) in [
+            ("static.bncsv", vec![], EncodingOptions::default()),
+            (
+                "context.bncsv",
+                vec!["-c", "context", "--index-interval", "100"],
+                EncodingOptions {
+                    codebook: Codebook::Context,
+                    index_interval: Some(100),
+                    ..EncodingOptions::default()
+                },
+            ),
+            (
+                "blocks.bncsv",
+                vec!["-b", "2048", "--index-interval", "64"],
+                EncodingOptions {
+                    block_size: Some(2048),
+                    index_interval: Some(64),
+                    ..EncodingOptions::default()
+                },
+            ),
+        ] {
+            let bncsv_path = root.join(name);
+            run_cli_command(
+                &vec![csv_path.to_str().unwrap().to_string()],
+                "csv",
+                Some(&bncsv_path),
+                None,
+            )
+            .args(&extra_args)
+            .assert()
+            .success();
+            // Written in pieces cutting the rows, the stream is the one of the cli
+            for chunk_len in [1, 100] {
+                let mut encoder = BncsvEncoder::with_options(&options, Vec::new());
+                csv.chunks(chunk_len)
+                    .for_each(|chunk| encoder.write_all(chunk).unwrap());
+                assert_eq!(encoder.finish().unwrap(), fs::read(&bncsv_path).unwrap());
+            }
+            // The decoder can be moved to another thread
+            let decoder = BncsvDecoder::new(File::open(&bncsv_path).unwrap());
+            let lines = std::thread::spawn(move || {
+                BufReader::new(decoder)
+                    .lines()
+                    .collect::<io::Result<Vec<String>>>()
+            })
+            .join()
+            .unwrap()
+            .unwrap();
+            assert_eq!(
+                lines.join("\n") + "\n",
+                String::from_utf8(csv.clone()).unwrap()
+            );
+        }
+    }
+    #[test]
+    fn test_decoder_read_error() {
+        // A reader failing right after a stream, or inside one, is reported after the rows decoded before
+        let root = TempDir::new().unwrap();
+        let csv = fs::read(create_random_csv(&root.join("data.csv"))).unwrap();
+        let stream = Utf8Converter::encode_slice(&csv).unwrap();
+        let data = stream.repeat(2);
+        for (failing, decoded_len) in [
+            (stream.len() as u64..u64::MAX, Some(csv.len())),
+            (100..u64::MAX, None),
+        ] {
+            let mut decoder = BncsvDecoder::new(FailingReader {
+                inner: Cursor::new(data.clone()),
+                failing: failing.clone(),
+            });
+            let mut decoded = Vec::new();
+            let error = decoder.read_to_end(&mut decoded).unwrap_err();
+            assert_eq!(
+                error.kind(),
+                io::ErrorKind::ConnectionReset,
+                "{:?}",
+                failing
+            );
+            assert!(csv.starts_with(&decoded), "{:?}", failing);
+            if let Some(len) = decoded_len {
+                assert_eq!(decoded.len(), len);
+            }
+        }
+        // The blocks read before the failure are decoded, each one once its checksum is verified
+        let options = EncodingOptions {
+            block_size: Some(2048),
+            ..EncodingOptions::default()
+        };
+        let mut stream = Vec::new();
+        Utf8Converter::encode_into_with_options(&options, &csv, &mut stream).unwrap();
+        let half = stream.len() as u64 / 2;
+        let mut decoder = BncsvDecoder::new(FailingReader {
+            inner: Cursor::new(stream.clone()),
+            failing: half..u64::MAX,
+        });
+        let mut decoded = Vec::new();
+        let error = decoder.read_to_end(&mut decoded).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionReset);
+        assert!(decoded.len() >= 2048 && csv.starts_with(&decoded));
+        // The first rows are read without reading the rest of the stream
+        let mut stream = Cursor::new(stream);
+        let mut first = [0; 100];
+        BncsvDecoder::new(&mut stream)
+            .read_exact(&mut first)
+            .unwrap();
+        assert_eq!(first, csv[..100]);
+        assert!(stream.position() < stream.get_ref().len() as u64 / 2);
+    }
+    #[test]
+    fn test_decode_row_range() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        let expected = csv.lines().skip(500).take(10).map(|l| format!("{}\n", l));
+        let expected = expected.collect::<String>();
+        for extra_args in [vec![], vec!["-b", "2048", "-c", "adaptive"]] {
+            let bncsv_path = root.join(format!("{}.bncsv", extra_args.len()));
+            run_cli_command(
+                &vec![csv_path.to_str().unwrap().to_string()],
+                "csv",
+                Some(&bncsv_path),
+                None,
+            )
+            .args(["--index-interval", "64"])
+            .args(&extra_args)
+            .assert()
+            .success();
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .args(["--rows", "500..510"])
+            .assert()
+            .success()
+            .stdout(expected.clone());
+        }
+        // Indexed streams without rows : only their header rows are written
+        let options = EncodingOptions {
+            block_size: Some(1000),
+            index_interval: Some(10),
+            ..Default::default()
+        };
+        for csv in [b"".as_slice(), b"a,b\n"] {
+            let mut bncsv = Vec::new();
+            Utf8Converter::encode_into_with_options(&options, csv, &mut bncsv).unwrap();
+            // The header row of a second stream is a row of the decoded csv
+            let concatenated = [bncsv.clone(), bncsv].concat();
+            let mut decoded = Vec::new();
+            Utf8Converter::decode_rows(&mut Cursor::new(concatenated), 0..5, &mut decoded).unwrap();
+            assert_eq!(decoded, [csv, csv].concat());
+        }
+    }
+    #[test]
+    fn test_decode_row_range_read_error() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        for extra_args in [
+            vec![],
+            vec!["-b", "2048"],
+            vec!["-c", "range"],
+            vec!["--columnar"],
+        ] {
+            let encoded = run_cli_command(
+                &vec![csv_path.to_str().unwrap().to_string()],
+                "csv",
+                None,
+                None,
+            )
+            .args(["--index-interval", "64"])
+            .args(&extra_args)
+            .assert()
+            .success();
+            let bncsv = encoded.get_output().stdout.clone();
+            // The row index footer is still readable, the body fails halfway
+            let half = bncsv.len() as u64 / 2;
+            let mut reader = FailingReader {
+                inner: Cursor::new(bncsv),
+                failing: half..half + 64,
+            };
+            let err =
+                Utf8Converter::decode_rows(&mut reader, 0..1024, &mut Vec::new()).unwrap_err();
+            assert_eq!(
+                err.kind(),
+                io::ErrorKind::ConnectionReset,
+                "{:?}",
+                extra_args
+            );
+        }
+    }
+    #[test]
+    fn test_decode_row_range_concatenated() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        let lines = csv
+            .lines()
+            .map(|l| format!("{}\n", l))
+            .collect::<Vec<String>>();
+        // Two streams with a header row, the second one ending without newline, then one without
+        let header_row = "a,b,c,d,e,f,g,h\n".to_string();
+        let parts = [
+            header_row.clone() + &lines[..300].concat(),
+            header_row + lines[300..700].concat().trim_end(),
+            lines[700..].concat(),
+        ];
+        for options in [
+            EncodingOptions::default(),
+            EncodingOptions {
+                codebook: Codebook::Adaptive,
+                block_size: Some(2048),
+                ..EncodingOptions::default()
+            },
+            EncodingOptions {
+                codebook: Codebook::Range,
+                ..EncodingOptions::default()
+            },
+            EncodingOptions {
+                columnar: true,
+                ..EncodingOptions::default()
+            },
+        ] {
+            for index_interval in [None, Some(64)] {
+                let options = EncodingOptions {
+                    index_interval,
+                    ..options.clone()
+                };
+                let mut bncsv = Vec::new();
+                for part in &parts {
+                    Utf8Converter::encode_into_with_options(&options, part.as_bytes(), &mut bncsv)
+                        .unwrap();
+                }
+                // Rows as numbered in the csv of the whole input, after its header row
+                let mut decoded = Vec::new();
+                Utf8Converter::decode_into_with_options(
+                    &DecodingOptions::default(),
+                    &bncsv,
+                    &mut decoded,
+                )
+                .unwrap();
+                let decoded = String::from_utf8(decoded).unwrap();
+                let (first_line, rows) = decoded.split_at(decoded.find('\n').unwrap() + 1);
+                let rows = rows.split_inclusive('\n').collect::<Vec<&str>>();
+                for range in [0..10, 295..305, 299..301, 690..710, 1020..1030, 0..2000] {
+                    let mut written = Vec::new();
+                    Utf8Converter::decode_rows(
+                        &mut Cursor::new(&bncsv),
+                        range.start as u64..range.end as u64,
+                        &mut written,
+                    )
+                    .unwrap();
+                    let expected = first_line.to_string()
+                        + &rows[range.start.min(rows.len())..range.end.min(rows.len())].concat();
+                    assert_eq!(
+                        String::from_utf8(written).unwrap(),
+                        expected,
+                        "{:?} {:?}",
+                        options.codebook,
+                        range
+                    );
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_decode_row_range_appended() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        let bncsv_path = root.join("data.bncsv");
+        run_cli_command(
+            &vec![csv_path.to_str().unwrap().to_string()],
+            "csv",
+            Some(&bncsv_path),
+            None,
+        )
+        .args(["-b", "2048", "--index-interval", "64"])
+        .assert()
+        .success();
+        let bncsv = fs::read(&bncsv_path).unwrap();
+        // A corrupted block of the second stream is reported at its position in the file
+        let mut concatenated = bncsv.clone();
+        concatenated.extend(&bncsv);
+        concatenated[bncsv.len() + 40] ^= 0xff;
+        fs::write(&bncsv_path, &concatenated).unwrap();
+        for extra_args in [vec![], vec!["--rows", "1024..1030"]] {
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .args(&extra_args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(format!(
+                "starting at byte {}",
+                bncsv.len() + 33
+            )));
+        }
+        concatenated[bncsv.len() + 40] ^= 0xff;
+        fs::write(&bncsv_path, &concatenated).unwrap();
+        let expected = csv.lines().chain(csv.lines()).skip(1020).take(10);
+        let expected = expected.map(|l| format!("{}\n", l)).collect::<String>();
+        run_cli_command(
+            &vec![bncsv_path.to_str().unwrap().to_string()],
+            "bncsv",
+            None,
+            None,
+        )
+        .args(["--rows", "1020..1030"])
+        .assert()
+        .success()
+        .stdout(expected);
+    }
+    #[test]
+    fn test_encode_decode_scientific_notation() {
+        let root = TempDir::new().unwrap();
+        let csv_path = root.join("scientific.csv");
+        let mut rng = rand::thread_rng();
+        let csv = (0..2000)
+            .map(|_| {
+                let x = rng.gen_range(-1e12..1e12_f64) * rng.gen_range(1e-30..1.0);
+                format!("{:e},{:E},{},+1.5e+07\n", x, x, x)
+            })
+            .collect::<String>();
+        fs::write(&csv_path, &csv).unwrap();
+        for codebook in ["static", "adaptive"] {
+            let bncsv_path = root.join(format!("{}.bncsv", codebook));
+            run_cli_command(
+                &vec![csv_path.to_str().unwrap().to_string()],
+                "csv",
+                Some(&bncsv_path),
+                None,
+            )
+            .args(["-c", codebook])
+            .assert()
+            .success();
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .assert()
+            .success()
+            .stdout(csv.clone());
+        }
+    }
+    #[test]
+    fn test_encode_decode_special_values() {
+        let root = TempDir::new().unwrap();
+        let csv_path = root.join("special.csv");
+        let values = [
+            "NaN",
+            "nan",
+            "inf",
+            "-inf",
+            "Inf",
+            "-Infinity",
+            "",
+            "1.5",
+            "-0.25",
+        ];
+        let mut rng = rand::thread_rng();
+        let csv = (0..2000)
+            .map(|_| {
+                let row = (0..4)
+                    .map(|_| values[rng.gen_range(0..values.len())])
+                    .collect::<Vec<&str>>();
+                format!("{}\n", row.join(","))
+            })
+            .collect::<String>();
+        fs::write(&csv_path, &csv).unwrap();
+        for codebook in ["static", "adaptive"] {
+            let bncsv_path = root.join(format!("{}.bncsv", codebook));
+            run_cli_command(
+                &vec![csv_path.to_str().unwrap().to_string()],
+                "csv",
+                Some(&bncsv_path),
+                None,
+            )
+            .args(["-c", codebook])
+            .assert()
+            .success();
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .assert()
+            .success()
+            .stdout(csv.clone());
+        }
+    }
+    #[test]
+    fn test_encode_decode_line_endings() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        let crlf_path = root.join("crlf.csv");
+        fs::write(&crlf_path, csv.replace('\n', "\r\n")).unwrap();
+        // Mostly CRLF, with a bare LF and a bare CR
+        let mixed = csv
+            .replace('\n', "\r\n")
+            .replacen("\r\n", "\n", 1)
+            .replacen("\r\n", "\r", 1);
+        let mixed_path = root.join("mixed.csv");
+        fs::write(&mixed_path, &mixed).unwrap();
+        for (path, extra_args, expected) in [
+            (&crlf_path, vec![], csv.replace('\n', "\r\n")),
+            (&mixed_path, vec!["-c", "adaptive"], mixed.clone()),
+            (&mixed_path, vec!["-b", "4096"], mixed.clone()),
+            (&mixed_path, vec!["--normalize-line-endings"], csv.clone()),
+        ] {
+            let bncsv_path = root.join("encoded.bncsv");
+            let encoded =
+                run_cli_command(&vec![path.to_str().unwrap().to_string()], "csv", None, None)
+                    .args(&extra_args)
+                    .assert()
+                    .success();
+            fs::write(&bncsv_path, &encoded.get_output().stdout).unwrap();
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .assert()
+            .success()
+            .stdout(expected);
+        }
+    }
+    #[test]
+    fn test_encode_decode_delimiters() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let csv = fs::read_to_string(&csv_path).unwrap();
+        // Decimal commas are only readable when the delimiter is not a comma
+        let semicolon = csv.replace(',', ";").replace('.', ",");
+        for (delimiter, content, extra_args) in [
+            (";", semicolon.clone(), vec!["--decimal-comma"]),
+            (
+                ";",
+                semicolon,
+                vec!["--decimal-comma", "-c", "adaptive", "-b", "4096"],
+            ),
+            ("tab", csv.replace(',', "\t"), vec![]),
+            ("|", csv.replace(',', "|"), vec!["--index-interval", "100"]),
+        ] {
+            let delimited_path = root.join("delimited.csv");
+            fs::write(&delimited_path, &content).unwrap();
+            let bncsv_path = root.join("encoded.bncsv");
+            let encoded = run_cli_command(
+                &vec![delimited_path.to_str().unwrap().to_string()],
+                "csv",
+                None,
+                None,
+            )
+            .args(["-d", delimiter])
+            .args(&extra_args)
+            .assert()
+            .success();
+            fs::write(&bncsv_path, &encoded.get_output().stdout).unwrap();
+            // The delimiter is read from the stream, no option is needed to decode
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .assert()
+            .success()
+            .stdout(content);
+        }
+    }
+    #[test]
+    fn test_encode_decode_header_row() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let rows = fs::read_to_string(&csv_path).unwrap();
+        let header_row = (0..rows.lines().next().unwrap().split(',').count())
+            .map(|i| format!("column_{}", i))
+            .collect::<Vec<String>>()
+            .join(",");
+        let csv = format!("{}\n{}", header_row, rows);
+        fs::write(&csv_path, &csv).unwrap();
+        let csv_arg = vec![csv_path.to_str().unwrap().to_string()];
+        for extra_args in [vec![], vec!["-c", "adaptive", "-b", "4096"]] {
+            let bncsv_path = root.join("encoded.bncsv");
+            let encoded = run_cli_command(&csv_arg, "csv", None, None)
+                .args(["--index-interval", "10"])
+                .args(&extra_args)
+                .assert()
+                .success();
+            fs::write(&bncsv_path, &encoded.get_output().stdout).unwrap();
+            let bncsv_arg = vec![bncsv_path.to_str().unwrap().to_string()];
+            run_cli_command(&bncsv_arg, "bncsv", None, None)
+                .assert()
+                .success()
+                .stdout(csv.clone());
+            // Row ranges don't count the header row, which is always written
+            let expected = format!(
+                "{}\n{}",
+                header_row,
+                rows.split_inclusive('\n')
+                    .skip(15)
+                    .take(10)
+                    .collect::<String>()
+            );
+            run_cli_command(&bncsv_arg, "bncsv", None, None)
+                .args(["--rows", "15..25"])
+                .assert()
+                .success()
+                .stdout(expected);
+        }
+        let bncsv_path = root.join("encoded.bncsv");
+        run_cli_command(
+            &vec![bncsv_path.to_str().unwrap().to_string()],
+            "bncsv",
+            None,
+            None,
+        )
+        .arg("--columns")
+        .assert()
+        .success()
+        .stdout(format!("{}\n", header_row.replace(',', "\n")));
+    }
+    #[test]
+    fn test_encode_decode_latin1_header_row() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        // A detected header row is kept as is, even when it isn't UTF-8
+        let mut csv = b"temp\xe9rature,pr\xe9cipitations\n".to_vec();
+        csv.extend(fs::read(&csv_path).unwrap());
+        fs::write(&csv_path, &csv).unwrap();
+        let csv_arg = vec![csv_path.to_str().unwrap().to_string()];
+        for extra_args in [vec![], vec!["-c", "adaptive", "-b", "4096"]] {
+            let bncsv_path = root.join("encoded.bncsv");
+            let encoded = run_cli_command(&csv_arg, "csv", None, None)
+                .args(["--index-interval", "10"])
+                .args(&extra_args)
+                .assert()
+                .success();
+            fs::write(&bncsv_path, &encoded.get_output().stdout).unwrap();
+            let bncsv_arg = vec![bncsv_path.to_str().unwrap().to_string()];
+            run_cli_command(&bncsv_arg, "bncsv", None, None)
+                .assert()
+                .success()
+                .stdout(csv.clone());
+            run_cli_command(&bncsv_arg, "bncsv", None, None)
+                .args(["--rows", "0..1"])
+                .assert()
+                .success()
+                .stdout(
+                    csv.split_inclusive(|&b| b == b'\n')
+                        .take(2)
+                        .flatten()
+                        .copied()
+                        .collect::<Vec<u8>>(),
+                );
+        }
+        // An explicit header row still has to be valid UTF-8
+        run_cli_command(&csv_arg, "csv", None, None)
+            .args(["--header-row", "present"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "The header row is not valid UTF-8",
+            ));
+    }
+    #[test]
+    fn test_encode_decode_escaped_bytes() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let rows = fs::read(&csv_path).unwrap();
+        // Stray bytes missing from the codebook : a BOM, a comment, spaces, quotes and invalid UTF-8
+        let mut csv = b"\xEF\xBB\xBF# exported values\n".to_vec();
+        csv.extend(&rows);
+        csv.extend(b"1.5, \"2\",3\xFF\n");
+        fs::write(&csv_path, &csv).unwrap();
+        let csv_arg = vec![csv_path.to_str().unwrap().to_string()];
+        for extra_args in [
+            vec![],
+            vec!["-c", "adaptive", "-b", "4096"],
+            vec!["-c", "range"],
+            vec!["-c", "context", "--columnar"],
+        ] {
+            let bncsv_path = root.join("encoded.bncsv");
+            let encoded = run_cli_command(&csv_arg, "csv", None, None)
+                .args(["--header-row", "absent"])
+                .args(&extra_args)
+                .assert()
+                .success()
+                .stderr(predicate::str::contains(
+                    "Escaped 21 bytes missing from the codebook",
+                ));
+            fs::write(&bncsv_path, &encoded.get_output().stdout).unwrap();
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .assert()
+            .success()
+            .stdout(csv.clone());
+        }
+    }
+    #[test]
+    fn test_encode_per_column_codebook() {
+        // Columns with their own digits : each one gets a fitted table, smaller than one table shared by the rows
+        let csv = (0..2000)
+            .map(|i: u64| {
+                let digits = (0..8).map(|d| (i * 2654435761) >> (3 * d));
+                let binary = digits
+                    .clone()
+                    .map(|x| (x % 2).to_string())
+                    .collect::<String>();
+                let high = digits.map(|x| (7 + x % 3).to_string()).collect::<String>();
+                format!("{},{}\n", binary, high)
+            })
+            .collect::<String>();
+        let encode = |options: EncodingOptions| {
+            let mut bncsv = Vec::new();
+            Utf8Converter::encode_into_with_options(&options, csv.as_bytes(), &mut bncsv).unwrap();
+            assert_eq!(Utf8Converter::decode_slice(&bncsv).unwrap(), csv.as_bytes());
+            bncsv
+        };
+        let per_column = encode(EncodingOptions {
+            codebook: Codebook::PerColumn,
+            ..Default::default()
+        });
+        let shared = encode(EncodingOptions {
+            codebook: Codebook::Adaptive,
+            ..Default::default()
+        });
+        assert_eq!(per_column[5], Codebook::PerColumn as u8);
+        let metadata = Utf8Converter::read_metadata(per_column.iter().copied()).unwrap();
+        // Both columns have their own code lengths, none fell back to the hard-coded codes
+        assert_eq!(metadata.column_tables.len(), 2);
+        assert!(metadata
+            .column_tables
+            .iter()
+            .all(|lengths| !lengths.is_empty()));
+        assert!(
+            per_column.len() < shared.len(),
+            "{} >= {}",
+            per_column.len(),
+            shared.len()
+        );
+    }
+    #[test]
+    fn test_encode_decode_columnar() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        // Ragged rows and a missing trailing newline are rebuilt as they were
+        let mut csv = b"time,temp,pressure\n".to_vec();
+        csv.extend(fs::read(&csv_path).unwrap());
+        csv.extend(b"1.5,2\n\n3,4,5,6");
+        fs::write(&csv_path, &csv).unwrap();
+        let csv_arg = vec![csv_path.to_str().unwrap().to_string()];
+        for extra_args in [
+            vec!["--columnar"],
+            vec!["--columnar", "-c", "adaptive", "-b", "4096"],
+            // Code tables of the columns stored in the metadata section
+            vec!["-c", "columns"],
+            vec!["-c", "columns", "-b", "4096"],
+        ] {
+            let bncsv_path = root.join("encoded.bncsv");
+            let encoded = run_cli_command(&csv_arg, "csv", None, None)
+                .args(["--index-interval", "10"])
+                .args(&extra_args)
+                .assert()
+                .success();
+            fs::write(&bncsv_path, &encoded.get_output().stdout).unwrap();
+            let bncsv_arg = vec![bncsv_path.to_str().unwrap().to_string()];
+            run_cli_command(&bncsv_arg, "bncsv", None, None)
+                .assert()
+                .success()
+                .stdout(csv.clone());
+            let lines = csv.split(|&b| b == b'\n').collect::<Vec<&[u8]>>();
+            // The header row is written before the range
+            let mut expected = [&lines[..1], &lines[13..20]].concat().join(&b'\n');
+            expected.push(b'\n');
+            run_cli_command(&bncsv_arg, "bncsv", None, None)
+                .args(["--rows", "12..19"])
+                .assert()
+                .success()
+                .stdout(expected);
+        }
+    }
+    #[test]
+    fn test_decode_rejects_invalid_columnar_runs() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let encoded = run_cli_command(
+            &vec![csv_path.to_str().unwrap().to_string()],
+            "csv",
+            None,
+            None,
+        )
+        .args(["--columnar", "--no-checksum"])
+        .assert()
+        .success();
+        let encoded = encoded.get_output().stdout.clone();
+        // The 1024 rows of 8 fields, followed by the empty row after the trailing newline
+        let runs = [
+            2_u32.to_le_bytes().as_slice(),
+            &8_u32.to_le_bytes(),
+            &1024_u64.to_le_bytes(),
+            &1_u32.to_le_bytes(),
+            &1_u64.to_le_bytes(),
+        ]
+        .concat();
+        let start = encoded.windows(runs.len()).position(|w| w == runs).unwrap();
+        for (fields, rows, message) in [
+            (0_u32, 1_u64 << 40, "run of rows without fields"),
+            (8, 1 << 40, "number of values does not match the rows"),
+            (8, 1023, "number of values does not match the rows"),
+            (
+                u32::MAX,
+                u64::MAX,
+                "number of values does not match the rows",
+            ),
+        ] {
+            let mut corrupted = encoded.clone();
+            corrupted[start + 4..start + 8].copy_from_slice(&fields.to_le_bytes());
+            corrupted[start + 8..start + 16].copy_from_slice(&rows.to_le_bytes());
+            let bncsv_path = root.join("corrupted.bncsv");
+            fs::write(&bncsv_path, corrupted).unwrap();
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(message));
+        }
+    }
+    #[test]
+    fn test_encode_decode_delta_columns() {
+        let root = TempDir::new().unwrap();
+        let csv_path = root.join("series.csv");
+        // Increasing timestamps, a fixed-scale price and random floats left as they are
+        let mut rng = rand::thread_rng();
+        let mut csv = b"time,price,value\n".to_vec();
+        (0..2000).for_each(|i| {
+            let row = format!(
+                "{},{:.2},{}\n",
+                1700000000 + 15 * i,
+                rng.gen_range(99.0..101.0),
+                rng.gen_range(-1000.0..1000.0)
+            );
+            csv.extend(row.as_bytes());
+        });
+        fs::write(&csv_path, &csv).unwrap();
+        let csv_arg = vec![csv_path.to_str().unwrap().to_string()];
+        let encoded_len = |extra_args: &[&str]| {
+            let bncsv_path = root.join("encoded.bncsv");
+            let encoded = run_cli_command(&csv_arg, "csv", None, None)
+                .args(["--columnar"])
+                .args(extra_args)
+                .assert()
+                .success();
+            fs::write(&bncsv_path, &encoded.get_output().stdout).unwrap();
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .assert()
+            .success()
+            .stdout(csv.clone());
+            encoded.get_output().stdout.len()
+        };
+        let columnar_len = encoded_len(&[]);
+        assert!(encoded_len(&["--delta", "auto"]) < columnar_len);
+        assert!(encoded_len(&["--delta", "0,1", "-c", "columns", "-b", "4096"]) < columnar_len);
+        // Floats with a varying number of digits can't be delta encoded
+        run_cli_command(&csv_arg, "csv", None, None)
+            .args(["--delta", "2"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "The column 2 can't be delta encoded",
+            ));
+        // Leaving the option out selects no column
+        run_cli_command(&csv_arg, "csv", None, None)
+            .args(["--delta", "none"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "Expected 'auto' or column indexes",
+            ));
+    }
+    #[test]
+    fn test_encode_decode_xor_floats() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        // Fields that aren't doubles in their shortest form stay in the text column
+        let mut csv = fs::read(&csv_path).unwrap();
+        csv.extend(b"1e-07,0.10,,nan,-0,-0.0,5,NaN\n");
+        fs::write(&csv_path, &csv).unwrap();
+        let csv_arg = vec![csv_path.to_str().unwrap().to_string()];
+        let bncsv_path = root.join("encoded.bncsv");
+        let mut encoded_lens = Vec::new();
+        for extra_args in [
+            vec!["--columnar"],
+            vec!["--xor-floats", "auto"],
+            vec!["--xor-floats", "0,3,7", "-c", "columns", "-b", "4096"],
+        ] {
+            let encoded = run_cli_command(&csv_arg, "csv", None, None)
+                .args(&extra_args)
+                .assert()
+                .success();
+            fs::write(&bncsv_path, &encoded.get_output().stdout).unwrap();
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .assert()
+            .success()
+            .stdout(csv.clone());
+            encoded_lens.push(encoded.get_output().stdout.len());
+        }
+        assert!(encoded_lens[1] < encoded_lens[0]);
+    }
+    #[test]
+    fn test_encode_decode_xor_exponent_floats() {
+        let root = TempDir::new().unwrap();
+        let csv_path = root.join("exponents.csv");
+        // Doubles printed with an exponent, with a few fields in the other forms
+        let mut rng = rand::thread_rng();
+        let mut csv = (0..2048)
+            .map(|_| {
+                let small = rng.gen_range(-1e-3..1e-3_f64);
+                let large = rng.gen_range(1e20..1e25_f64);
+                format!("{:e},{:e},{}\n", small, large, small)
+            })
+            .collect::<String>();
+        csv.push_str("1e-07,1.5E+20,5e0\n0.5,-0e0,NaN\n");
+        fs::write(&csv_path, &csv).unwrap();
+        let csv_arg = vec![csv_path.to_str().unwrap().to_string()];
+        let bncsv_path = root.join("encoded.bncsv");
+        let mut encoded_lens = Vec::new();
+        for extra_args in [
+            vec!["--columnar"],
+            vec!["--xor-floats", "0,1,2"],
+            vec!["--xor-floats", "auto", "-c", "columns", "-b", "4096"],
+        ] {
+            let encoded = run_cli_command(&csv_arg, "csv", None, None)
+                .args(&extra_args)
+                .assert()
+                .success();
+            fs::write(&bncsv_path, &encoded.get_output().stdout).unwrap();
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .assert()
+            .success()
+            .stdout(csv.clone());
+            encoded_lens.push(encoded.get_output().stdout.len());
+        }
+        // The 17 digits of these doubles take more than their 64 bits as text
+        assert!(
+            encoded_lens[1] < encoded_lens[0] * 4 / 5,
+            "{:?}",
+            encoded_lens
+        );
+    }
+    #[test]
+    fn test_encode_decode_scaled_columns() {
+        let root = TempDir::new().unwrap();
+        let csv_path = root.join("prices.csv");
+        // Prices with two decimals, along with fields that aren't written back from their number
+        let mut csv = (0..2048)
+            .map(|i| format!("{},{}.{:02}\n", i, 100 + (i * 7919) % 9000, (i * 31) % 100))
+            .collect::<String>()
+            .into_bytes();
+        csv.extend(b"2048,007.50\n2049,+1.00\n2050,-0.00\n2051,42.9\n2052,\n2053,1e5\n");
+        fs::write(&csv_path, &csv).unwrap();
+        let csv_arg = vec![csv_path.to_str().unwrap().to_string()];
+        let bncsv_path = root.join("encoded.bncsv");
+        let mut encoded_lens = Vec::new();
+        for extra_args in [
+            vec!["--columnar"],
+            vec!["--scaled", "auto"],
+            vec![
+                "--scaled", "1", "--delta", "0", "-c", "columns", "-b", "4096",
+            ],
+        ] {
+            let encoded = run_cli_command(&csv_arg, "csv", None, None)
+                .args(&extra_args)
+                .assert()
+                .success();
+            fs::write(&bncsv_path, &encoded.get_output().stdout).unwrap();
+            run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .assert()
+            .success()
+            .stdout(csv.clone());
+            encoded_lens.push(encoded.get_output().stdout.len());
+        }
+        assert!(encoded_lens[1] < encoded_lens[0]);
+        // A column can't have two encodings
+        run_cli_command(&csv_arg, "csv", None, None)
+            .args(["--scaled", "0", "--delta", "0"])
+            .assert()
+            .failure();
+    }
+    #[test]
+    fn test_decode_detects_corruption() {
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("data.csv"));
+        let csv = fs::read(&csv_path).unwrap();
+        // Flips a bit in the first block, in a later block, in the body without blocks,
+        // then in the trailing csv checksum. Only the rows of the blocks before the corrupted one are output.
+        // A flipped bit of the body without blocks can also make an invalid code, found before the checksum.
+        let first_block: fn(usize) -> usize = |_| 100;
+        let middle: fn(usize) -> usize = |len| len / 2;
+        let last: fn(usize) -> usize = |len| len - 1;
+        for (extra_args, corrupted_byte, message, decoded_blocks) in [
+            (
+                vec!["-b", "4096"],
+                first_block,
+                "block starting at byte 33",
+                false,
+            ),
+            (vec!["-b", "4096"], middle, "checksum mismatch", true),
+            (vec![], middle, "Corrupted bncsv|Invalid character", false),
+            (vec![], last, "body between bytes 7 and", false),
+        ] {
+            let bncsv_path = root.join(format!("{}.bncsv", extra_args.len()));
+            run_cli_command(
+                &vec![csv_path.to_str().unwrap().to_string()],
+                "csv",
+                Some(&bncsv_path),
+                None,
+            )
+            .args(&extra_args)
+            .assert()
+            .success();
+            let mut data = fs::read(&bncsv_path).unwrap();
+            let corrupted_byte = corrupted_byte(data.len());
+            data[corrupted_byte] ^= 0b0001_0000;
+            fs::write(&bncsv_path, data).unwrap();
+            let decoded = run_cli_command(
+                &vec![bncsv_path.to_str().unwrap().to_string()],
+                "bncsv",
+                None,
+                None,
+            )
+            .assert()
+            .failure()
+            .stderr(predicate::str::is_match(message).unwrap());
+            let stdout = &decoded.get_output().stdout;
+            assert!(csv.starts_with(stdout), "{:?}", extra_args);
+            assert_eq!(!stdout.is_empty(), decoded_blocks, "{:?}", extra_args);
+        }
+        // Flips a bit of the header row stored in the metadata section, nothing is output
+        let mut with_header = b"time,temp\n".to_vec();
+        with_header.extend(&csv);
+        fs::write(&csv_path, &with_header).unwrap();
+        let bncsv_path = root.join("header.bncsv");
+        run_cli_command(
+            &vec![csv_path.to_str().unwrap().to_string()],
+            "csv",
+            Some(&bncsv_path),
+            None,
+        )
+        .assert()
+        .success();
+        let mut data = fs::read(&bncsv_path).unwrap();
+        let header_text = data.windows(4).position(|w| w == b"time").unwrap();
+        data[header_text + 2] ^= 0b0000_0100;
+        fs::write(&bncsv_path, data).unwrap();
+        run_cli_command(
+            &vec![bncsv_path.to_str().unwrap().to_string()],
+            "bncsv",
+            None,
+            None,
+        )
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Corrupted bncsv metadata : checksum of the section",
+        ))
+        .stdout("");
+    }
+    #[test]
+    fn test_overwrite_longer_output() {
+        // An existing output longer than the converted data must not keep its trailing bytes
+        let root = TempDir::new().unwrap();
+        let csv_path = create_random_csv(&root.join("test.csv"));
+        let bncsv_path = root.join("test.bncsv");
+        let decoded_path = root.join("decoded.csv");
+        let stale = vec![b'x'; 4 * fs::metadata(&csv_path).unwrap().len() as usize];
+        fs::write(&bncsv_path, &stale).unwrap();
+        fs::write(&decoded_path, &stale).unwrap();
+
+        run_cli_command(
+            &vec![csv_path.to_str().unwrap().to_string()],
+            "csv",
+            Some(&bncsv_path),
+            None,
+        )
+        .assert()
+        .success();
+        run_cli_command(
+            &vec![bncsv_path.to_str().unwrap().to_string()],
+            "bncsv",
+            Some(&decoded_path),
+            None,
+        )
+        .assert()
+        .success();
+        assert!(fs::metadata(&bncsv_path).unwrap().len() < stale.len() as u64);
+        assert_eq!(
+            fs::read(&csv_path).unwrap(),
+            fs::read(&decoded_path).unwrap()
+        );
+    }
+
     #[test]
     fn test_encode_decode() {
         let root = TempDir::new().unwrap();